@@ -0,0 +1,302 @@
+use anyhow::bail;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// Chance of an extra path from a node to the next node over in the following layer.
+const EXTRA_PATH_CHANCE: f64 = 0.3;
+
+/// How often each kind shows up in the layers between the first battles and the rest before
+/// the boss.
+const NODE_WEIGHTS: [(NodeKind, u32); 5] = [
+    (NodeKind::Battle, 5),
+    (NodeKind::EliteBattle, 2),
+    (NodeKind::Shrine, 1),
+    (NodeKind::Merchant, 1),
+    (NodeKind::CraftingCamp, 1),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Battle,
+    EliteBattle,
+    /// Heals the party to full HP.
+    Shrine,
+    /// There is no currency yet, so the merchant hands over one item from its stock.
+    Merchant,
+    /// Opens the workshop.
+    CraftingCamp,
+    Boss,
+}
+
+/// Shape of generated maps and what their nodes are filled with, from
+/// `data/content/campaign.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AdventureConfig {
+    /// Number of layers including the boss layer.
+    pub layers: usize,
+    pub min_width: usize,
+    pub max_width: usize,
+    /// Encounter ids per battle node kind.
+    pub battles: Vec<String>,
+    pub elite_battles: Vec<String>,
+    pub bosses: Vec<String>,
+    /// Item template ids merchants can offer.
+    pub merchant_stock: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MapNode {
+    pub kind: NodeKind,
+    pub layer: usize,
+    /// Encounter started by battle nodes.
+    pub encounter: Option<String>,
+    /// Item template a merchant offers.
+    pub item: Option<String>,
+    /// Nodes in the next layer this one leads to.
+    pub next: Vec<usize>,
+}
+
+/// Campaign map: layers of nodes the party moves through one at a time, from a choice of
+/// battles at the start to a single boss at the end.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdventureMap {
+    pub seed: u64,
+    pub nodes: Vec<MapNode>,
+    /// Visited nodes in order. The last one is where the party is.
+    pub path: Vec<usize>,
+}
+
+/// Hands out ids from a pool in a shuffled order, refilling once all were used, so nodes
+/// don't repeat an encounter until the pool runs out.
+struct Bag<'a> {
+    pool: &'a [String],
+    left: Vec<String>,
+}
+
+impl<'a> Bag<'a> {
+    fn new(pool: &'a [String]) -> Self {
+        Self {
+            pool,
+            left: Vec::new(),
+        }
+    }
+
+    fn take(&mut self, rng: &mut ChaCha8Rng) -> Option<String> {
+        if self.left.is_empty() {
+            self.left = self.pool.to_vec();
+            self.left.shuffle(rng);
+        }
+        self.left.pop()
+    }
+}
+
+impl AdventureMap {
+    pub fn generate(config: &AdventureConfig, seed: u64) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut battles = Bag::new(&config.battles);
+        let mut elites = Bag::new(&config.elite_battles);
+        let mut bosses = Bag::new(&config.bosses);
+        let layer_count = config.layers.max(2);
+
+        let mut nodes = Vec::new();
+        let mut layers: Vec<Vec<usize>> = Vec::new();
+        for layer in 0..layer_count {
+            let width = if layer == layer_count - 1 {
+                1
+            } else {
+                rng.gen_range(config.min_width..=config.max_width.max(config.min_width))
+                    .max(1)
+            };
+            let mut ids = Vec::new();
+            for _ in 0..width {
+                let kind = if layer == 0 {
+                    NodeKind::Battle
+                } else if layer == layer_count - 1 {
+                    NodeKind::Boss
+                } else if layer == layer_count - 2 {
+                    NodeKind::Shrine
+                } else {
+                    // No elites right after the first fight.
+                    let weights = NODE_WEIGHTS
+                        .iter()
+                        .filter(|(kind, _)| layer > 1 || *kind != NodeKind::EliteBattle);
+                    weights
+                        .collect::<Vec<_>>()
+                        .choose_weighted(&mut rng, |(_, weight)| *weight)
+                        .map(|(kind, _)| *kind)
+                        .unwrap_or(NodeKind::Battle)
+                };
+                let encounter = match kind {
+                    NodeKind::Battle => battles.take(&mut rng),
+                    NodeKind::EliteBattle => elites.take(&mut rng),
+                    NodeKind::Boss => bosses.take(&mut rng),
+                    _ => None,
+                };
+                let item = match kind {
+                    NodeKind::Merchant => config.merchant_stock.choose(&mut rng).cloned(),
+                    _ => None,
+                };
+                ids.push(nodes.len());
+                nodes.push(MapNode {
+                    kind,
+                    layer,
+                    encounter,
+                    item,
+                    next: Vec::new(),
+                });
+            }
+            layers.push(ids);
+        }
+
+        for pair in layers.windows(2) {
+            connect(&mut nodes, &pair[0], &pair[1], &mut rng);
+        }
+
+        Self {
+            seed,
+            nodes,
+            path: Vec::new(),
+        }
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.nodes.iter().map(|n| n.layer + 1).max().unwrap_or(0)
+    }
+
+    pub fn layer(&self, layer: usize) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].layer == layer)
+            .collect()
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.path.last().copied()
+    }
+
+    /// Nodes the party can move to next.
+    pub fn choices(&self) -> Vec<usize> {
+        match self.current() {
+            Some(current) => self.nodes[current].next.clone(),
+            None => self.layer(0),
+        }
+    }
+
+    /// The party has reached the end of the map.
+    pub fn is_finished(&self) -> bool {
+        self.current()
+            .is_some_and(|current| self.nodes[current].next.is_empty())
+    }
+
+    /// Node the party is allowed to move to right now.
+    pub fn reachable(&self, id: usize) -> anyhow::Result<&MapNode> {
+        if !self.choices().contains(&id) {
+            bail!("node {} can't be reached from here", id);
+        }
+        Ok(&self.nodes[id])
+    }
+}
+
+/// Links two neighbouring layers so every node has a way forward and a way in. Nodes are
+/// matched by their relative position, so paths rarely cross.
+fn connect(nodes: &mut [MapNode], from: &[usize], to: &[usize], rng: &mut ChaCha8Rng) {
+    let scale = |idx: usize, len: usize, other_len: usize| -> usize {
+        if len == 1 {
+            (other_len - 1) / 2
+        } else {
+            (idx * (other_len - 1) + (len - 1) / 2) / (len - 1)
+        }
+    };
+
+    for (i, &node) in from.iter().enumerate() {
+        let target = scale(i, from.len(), to.len());
+        nodes[node].next.push(to[target]);
+        if target + 1 < to.len() && rng.gen_bool(EXTRA_PATH_CHANCE) {
+            nodes[node].next.push(to[target + 1]);
+        }
+    }
+    for (k, &node) in to.iter().enumerate() {
+        let source = from[scale(k, to.len(), from.len())];
+        nodes[source].next.push(node);
+    }
+    for &node in from {
+        nodes[node].next.sort_unstable();
+        nodes[node].next.dedup();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AdventureConfig {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+        AdventureConfig {
+            layers: 6,
+            min_width: 2,
+            max_width: 4,
+            battles: ids(&["ambush", "crossing", "nest"]),
+            elite_battles: ids(&["patrol"]),
+            bosses: ids(&["warband"]),
+            merchant_stock: ids(&["potion"]),
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_map() {
+        let a = AdventureMap::generate(&config(), 7);
+        let b = AdventureMap::generate(&config(), 7);
+
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            serde_json::to_string(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn every_node_is_on_a_path_to_the_boss() {
+        for seed in 0..20 {
+            let map = AdventureMap::generate(&config(), seed);
+            let last = map.layer_count() - 1;
+
+            assert_eq!(map.layer(last).len(), 1);
+            assert_eq!(map.nodes[map.layer(last)[0]].kind, NodeKind::Boss);
+            for (id, node) in map.nodes.iter().enumerate() {
+                if node.layer < last {
+                    assert!(!node.next.is_empty());
+                    assert!(node
+                        .next
+                        .iter()
+                        .all(|&n| map.nodes[n].layer == node.layer + 1));
+                }
+                if node.layer > 0 {
+                    assert!(map.nodes.iter().any(|n| n.next.contains(&id)));
+                }
+                assert_eq!(
+                    matches!(
+                        node.kind,
+                        NodeKind::Battle | NodeKind::EliteBattle | NodeKind::Boss
+                    ),
+                    node.encounter.is_some()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_connected_nodes_are_reachable() {
+        let mut map = AdventureMap::generate(&config(), 3);
+        let last = map.nodes.len() - 1;
+
+        assert!(map.reachable(last).is_err());
+        let start = map.layer(0)[0];
+        map.reachable(start).unwrap();
+        map.path.push(start);
+
+        let next = map.nodes[start].next[0];
+        map.reachable(next).unwrap();
+        assert!(map.reachable(start).is_err());
+        assert!(!map.is_finished());
+    }
+}
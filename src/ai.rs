@@ -1,73 +1,843 @@
+use crate::battlefield::Terrain;
 use crate::battlestate::{BattleState, UnitRef};
+use crate::character::{Ability, Stats};
+use crate::effect::Effect;
 use crate::hexgrid::Hex;
-use crate::pathfinding::bfs_path;
+use crate::pathfinding::{hex_neighbors, movement_range};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 
-pub fn hex_distance(a: Hex, b: Hex) -> i32 {
-    ((a.q - b.q).abs() + (a.q + a.r - b.q - b.r).abs() + (a.r - b.r).abs()) / 2
+/// What a unit did (or, for heroes, would do) on its turn. Enemy decisions are recorded in the
+/// battle log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AiDecision {
+    pub unit: UnitRef,
+    pub move_to: Hex,
+    pub ability: Option<usize>,
+    pub target: Option<UnitRef>,
 }
 
-pub fn enemy_ai(battle: &mut BattleState, enemy: UnitRef) {
-    let target_hex = enemy_ai_move(battle, enemy);
-    let enemy_hex = battle.unit_hex(enemy).unwrap(); // It has to exist if that function is called.
+/// How much an enemy archetype cares about each part of a move. Set per archetype in
+/// `data/content/enemies.json`, anything left out keeps its default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiWeights {
+    /// Per point of expected damage dealt. Overkill doesn't count.
+    pub damage: f32,
+    /// Times the chance that the attack kills the target.
+    pub kill: f32,
+    /// Times the value of the ability's effect, see `effect_value`.
+    pub effect: f32,
+    /// Times the share of HP the target has already lost, so wounded heroes get finished first.
+    pub focus: f32,
+    /// Per point of damage the heroes could deal to the unit on that hex next turn.
+    pub exposure: f32,
+    /// Times the value of the terrain under the hex, see `terrain_value`.
+    pub terrain: f32,
+    /// Per hex closer to the nearest hero, for hexes with nothing to attack from.
+    pub advance: f32,
+    /// Bonus for staying where the unit is.
+    pub hold: f32,
+    /// Share of max HP at or below which the unit retreats: it moves away from the heroes and
+    /// weighs exposure `RETREAT_EXPOSURE` times as much.
+    pub retreat_below: f32,
+    /// Per other enemy within `ALLY_RANGE` hexes.
+    pub allies: f32,
+    /// Per other enemy already next to the target, so melee units close in around one hero.
+    pub surround: f32,
+}
 
-    battle.move_unit(enemy, target_hex, 0); // Movement cost doesnt matter. Target hex takes it into consideration. May change later
+impl Default for AiWeights {
+    fn default() -> Self {
+        Self {
+            damage: 1.0,
+            kill: 40.0,
+            effect: 1.0,
+            focus: 10.0,
+            exposure: 0.5,
+            terrain: 1.0,
+            advance: 2.0,
+            hold: 1.5,
+            retreat_below: 0.25,
+            allies: 0.0,
+            surround: 4.0,
+        }
+    }
+}
 
-    let enemy_hex = battle.unit_hex(enemy).unwrap(); // Updated after move
+/// Named way of fighting, set per archetype in `data/content/enemies.json` and overridden per
+/// enemy in `data/content/encounters.json`. It adjusts the archetype's weights.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum AiBehavior {
+    /// Uses the archetype's weights as they are.
+    #[default]
+    Standard,
+    /// Charges in, ignores retaliation and never retreats.
+    Berserker,
+    /// Keeps out of the heroes' reach and makes more use of terrain.
+    Skirmisher,
+    /// Never goes more than `radius` hexes from the hex it started the battle on.
+    Guardian { radius: i32 },
+    /// Cautious and quick to run.
+    Coward,
+    /// Prefers abilities with effects and stays close to the other enemies.
+    SupportCaster,
+}
 
-    let attack_area = enemy_hex.range_to_area(1, battle.grid_width, battle.grid_height); // Placeholder for enemy attack range
-    let (_, attackable_heroes) = battle.units_in_area(enemy, attack_area);
+impl AiBehavior {
+    pub fn adjust(self, weights: &AiWeights) -> AiWeights {
+        let mut weights = weights.clone();
+        match self {
+            AiBehavior::Standard => {}
+            AiBehavior::Berserker => {
+                weights.exposure = 0.0;
+                weights.retreat_below = 0.0;
+                weights.hold = 0.0;
+                weights.advance *= 2.0;
+                weights.kill *= 1.5;
+            }
+            AiBehavior::Skirmisher => {
+                weights.exposure *= 2.0;
+                weights.terrain *= 2.0;
+                weights.hold = 0.0;
+            }
+            AiBehavior::Guardian { .. } => weights.retreat_below = 0.0,
+            AiBehavior::Coward => {
+                weights.exposure *= 2.0;
+                weights.retreat_below = weights.retreat_below.max(0.6);
+            }
+            AiBehavior::SupportCaster => {
+                weights.effect *= 3.0;
+                weights.damage *= 0.5;
+                weights.exposure *= 1.5;
+                weights.allies = weights.allies.max(2.0);
+            }
+        }
+        weights
+    }
+}
 
-    let target = attackable_heroes
-        .iter()
-        .copied()
-        .filter(|u| matches!(u, UnitRef::Hero(_)))
-        .min_by_key(|&u| battle.unit_current_health(u));
+/// Global setting for how well enemies play and how strong they are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    /// Scales enemy HP and damage.
+    pub fn scale_stats(self, stats: &mut Stats) {
+        let scale = match self {
+            Difficulty::Easy => 0.8,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.25,
+        };
+        let scaled = |value: i32| ((value as f32 * scale).round() as i32).max(1);
+        stats.max_hp = scaled(stats.max_hp);
+        stats.hp = scaled(stats.hp);
+        stats.damage = (scaled(stats.damage.0), scaled(stats.damage.1));
+    }
+
+    /// How much the AI minds the heroes hitting back.
+    fn awareness(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.0,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        }
+    }
+
+    /// Moves scoring within this many points of the best one are all fair picks.
+    fn slack(self) -> f32 {
+        match self {
+            Difficulty::Easy => 15.0,
+            Difficulty::Normal | Difficulty::Hard => 0.0,
+        }
+    }
+}
 
-    if let Some(hero) = target {
-        enemy_ai_attack_hero(battle, enemy, hero);
+const RETREAT_EXPOSURE: f32 = 3.0;
+
+/// Other enemies within this many hexes count for `AiWeights::allies`.
+const ALLY_RANGE: i32 = 2;
+
+/// Extra movement the long-range plan charges for passing another enemy's hex. That enemy
+/// will likely have moved on by then, but going around beats queueing behind it.
+const ALLY_BLOCK_COST: i32 = 3;
+
+/// Heroes that have to walk to reach a hex count for this share of their damage.
+const WALKING_THREAT: f32 = 0.5;
+
+/// Best move for a unit this turn: where to go, what it costs and what to attack from there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Plan {
+    move_to: Hex,
+    cost: i32,
+    attack: Option<(usize, UnitRef)>,
+}
+
+pub fn hex_distance(a: Hex, b: Hex) -> i32 {
+    a.distance(b)
+}
+
+pub fn enemy_ai(battle: &mut BattleState, enemy: UnitRef) -> AiDecision {
+    let weights = ai_weights(battle, enemy);
+    let plan = plan_turn(battle, enemy, &weights);
+
+    battle.move_unit(enemy, plan.move_to, plan.cost);
+
+    if let Some((ability_idx, hero)) = plan.attack {
+        battle.use_ability(enemy, ability_idx, hero);
+    }
+
+    AiDecision {
+        unit: enemy,
+        move_to: plan.move_to,
+        ability: plan.attack.map(|(idx, _)| idx),
+        target: plan.attack.map(|(_, hero)| hero),
+    }
+}
+
+/// Plans a hero's turn the way enemies plan theirs, without carrying it out. Used by the
+/// simulator to play the party. Heroes fight to the end unless `behavior` says otherwise, and
+/// the difficulty only applies to enemies.
+pub fn hero_ai(battle: &mut BattleState, hero: UnitRef, behavior: AiBehavior) -> AiDecision {
+    let weights = AiWeights {
+        retreat_below: 0.0,
+        ..AiWeights::default()
+    };
+    let plan = plan_turn(battle, hero, &behavior.adjust(&weights));
+    AiDecision {
+        unit: hero,
+        move_to: plan.move_to,
+        ability: plan.attack.map(|(idx, _)| idx),
+        target: plan.attack.map(|(_, target)| target),
     }
 }
 
-/// Return hex to move to for enemy AI. (closest hero)
-/// If no movement or path is possible, returns current position.
-pub fn enemy_ai_move(battle: &BattleState, enemy: UnitRef) -> Hex {
+/// Scores every hex the unit can reach together with the best attack from it and picks the
+/// highest. Besides the attack the score counts how exposed the hex is to the other side, its
+/// terrain, and how far it is from the other side: farther is better when retreating, and when
+/// there is nothing to hit, the less walking is left to a hex to attack from (over as many
+/// turns as it takes, see `approach_costs`) the better. Exposure is not counted on the way in.
+fn plan_turn(battle: &mut BattleState, enemy: UnitRef, weights: &AiWeights) -> Plan {
     let start = battle.unit_hex(enemy).unwrap(); // It has to exist if that function is called.
-    let movement = battle.unit_movement(enemy).unwrap(); // It has to exist if that function is called.
+    let movement = battle.unit_current_movement(enemy).unwrap(); // It has to exist if that function is called.
+    let opponents = opponents(battle, enemy);
+    let retreating = health_share(battle, enemy) <= weights.retreat_below;
+    let post = guarded_post(battle, enemy);
+    let approach = approach_costs(battle, enemy);
 
-    if movement <= 0 {
-        return start;
+    let mut options = Vec::new();
+    let full_movement = battle.unit_movement(enemy).unwrap();
+    for (hex, (cost, _)) in movement_range(start, movement.max(0), full_movement, battle) {
+        if post.is_some_and(|(post, radius)| hex != start && hex_distance(hex, post) > radius) {
+            continue;
+        }
+
+        let attack = best_attack_from(battle, enemy, hex, weights);
+        let mut score = attack.map_or(0.0, |(score, _)| score);
+
+        let nearest = opponents
+            .iter()
+            .filter_map(|&o| battle.unit_hex(o))
+            .map(|o| hex_distance(hex, o))
+            .min()
+            .unwrap_or(0);
+        if retreating {
+            score += weights.advance * nearest as f32;
+        } else if attack.is_none() {
+            // Walls and other units can make the nearest opponent far away on foot.
+            let remaining = approach.get(&hex).copied().unwrap_or(nearest * 2 + 10);
+            score -= weights.advance * remaining as f32;
+        }
+
+        let exposure_weight = if retreating {
+            weights.exposure * RETREAT_EXPOSURE
+        } else if attack.is_none()
+            && matches!((approach.get(&hex), approach.get(&start)), (Some(to), Some(from)) if to < from)
+        {
+            // Closing in on a hex to attack from is worth some risk, or units with nothing
+            // to hit yet would wait just outside the heroes' reach.
+            0.0
+        } else {
+            weights.exposure
+        };
+        score -= exposure_weight * exposure(battle, enemy, hex);
+        score += weights.terrain
+            * battle
+                .hex_map
+                .get(&hex)
+                .map_or(0.0, |i| terrain_value(i.terrain));
+        score += weights.allies * allies_within(battle, enemy, hex, ALLY_RANGE) as f32;
+        if hex == start {
+            score += weights.hold;
+        }
+
+        let plan = Plan {
+            move_to: hex,
+            cost,
+            attack: attack.map(|(_, attack)| attack),
+        };
+        // Scores are compared in hundredths so that equal options tie exactly.
+        options.push((-(score * 100.0).round() as i64, plan));
+    }
+
+    // Easier difficulties let enemies pick from everything close enough to the best move.
+    let slack = match enemy {
+        UnitRef::Enemy(_) => (battle.difficulty.slack() * 100.0).round() as i64,
+        UnitRef::Hero(_) => 0,
+    };
+    let lowest = options.iter().map(|&(key, _)| key).min().unwrap_or(0);
+    options.retain(|&(key, _)| key <= lowest + slack);
+    options.sort();
+
+    battle.choose(&options).map_or(
+        Plan {
+            move_to: start,
+            cost: 0,
+            attack: None,
+        },
+        |(_, plan)| plan,
+    )
+}
+
+/// Best scoring ability and hero the unit could hit if it stood on `hex`. Ties go to the
+/// lowest ability index and unit, the move as a whole is tie-broken by the battle RNG.
+fn best_attack_from(
+    battle: &BattleState,
+    enemy: UnitRef,
+    hex: Hex,
+    weights: &AiWeights,
+) -> Option<(f32, (usize, UnitRef))> {
+    let abilities = battle.unit_abilities(enemy)?;
+    let targets: Vec<_> = opponents(battle, enemy)
+        .into_iter()
+        .filter_map(|o| Some((o, battle.unit_hex(o)?)))
+        .collect();
+
+    let mut options = Vec::new();
+    for (idx, ability) in abilities.iter().enumerate() {
+        for &(hero, hero_hex) in &targets {
+            if hex_distance(hex, hero_hex) <= ability.range
+                && battle.has_line_of_sight(enemy, hex, hero_hex, ability.line_of_sight)
+            {
+                let score = attack_score(battle, enemy, ability, hero, weights);
+                options.push((-(score * 100.0).round() as i64, idx, hero, score));
+            }
+        }
     }
 
-    let closest_hero_hex = battle
-        .heroes
-        .iter()
-        .map(|(_i, h)| h.hex)
-        .min_by_key(|&h| hex_distance(start, h));
+    options
+        .into_iter()
+        .min_by_key(|&(key, idx, hero, _)| (key, idx, hero))
+        .map(|(_, idx, hero, score)| (score, (idx, hero)))
+}
 
-    let target = match closest_hero_hex {
-        Some(h) => h,
-        None => return start,
+/// Damage that actually lands (overkill is wasted), the chance of finishing the hero off, the
+/// value of the ability's effect and how wounded the hero already is.
+fn attack_score(
+    battle: &BattleState,
+    enemy: UnitRef,
+    ability: &Ability,
+    hero: UnitRef,
+    weights: &AiWeights,
+) -> f32 {
+    let hp = battle.unit_current_health(hero) as f32;
+    let damage = battle.expected_damage(enemy, hero, ability.damage_modifier);
+    let kill_chance = battle.kill_chance(enemy, hero, ability.damage_modifier);
+    let effect = ability.effect.as_ref().map_or(0.0, effect_value);
+    let wounded = 1.0 - health_share(battle, hero);
+    let surrounding = battle
+        .unit_hex(hero)
+        .map_or(0, |hex| allies_within(battle, enemy, hex, 1));
+
+    weights.damage * damage.min(hp)
+        + weights.kill * kill_chance
+        + weights.effect * effect
+        + weights.focus * wounded
+        + weights.surround * surrounding as f32
+}
+
+/// Movement it takes to get from each hex to the nearest free hex the unit could attack an
+/// opponent from, with a clear line where the ability needs one. Opponents block the way. Allies
+/// don't, since they move too, but cost `ALLY_BLOCK_COST` extra, so units spread around a target
+/// instead of lining up behind each other.
+fn approach_costs(battle: &BattleState, enemy: UnitRef) -> HashMap<Hex, i32> {
+    let abilities = battle.unit_abilities(enemy).unwrap_or_default();
+    let full_movement = battle.unit_movement(enemy).unwrap_or(1).max(1);
+    // Cost of stepping onto a hex, `None` if the unit can't stand there.
+    let enter_cost = |hex: Hex| -> Option<i32> {
+        let info = battle.hex_map.get(&hex)?;
+        let cost = info.terrain.movement_cost()?.min(full_movement);
+        match info.occupying_unit {
+            Some(other) if !same_side(other, enemy) => None,
+            Some(other) if other != enemy => Some(cost + ALLY_BLOCK_COST),
+            _ => Some(cost),
+        }
     };
 
-    let path = bfs_path(start, target, battle);
-    println!("PATH{:?}", path);
+    let targets: Vec<Hex> = opponents(battle, enemy)
+        .into_iter()
+        .filter_map(|o| battle.unit_hex(o))
+        .collect();
 
-    path.iter()
-        .skip(1) // ignore starting hex
-        .take(movement as usize)
-        .take_while(|&&hex| battle.is_hex_passable(hex))
-        .last()
+    let mut costs: HashMap<Hex, i32> = HashMap::new();
+    let mut frontier = BinaryHeap::new();
+    let mut goals: Vec<Hex> = battle
+        .hex_map
+        .keys()
         .copied()
-        .unwrap_or(start)
+        .filter(|&hex| {
+            abilities.iter().any(|a| {
+                targets.iter().any(|&t| {
+                    hex_distance(hex, t) <= a.range
+                        && battle.has_line_of_sight(enemy, hex, t, a.line_of_sight)
+                })
+            })
+        })
+        .collect();
+    goals.sort();
+    for hex in goals {
+        // An ally standing there doesn't stop the unit from passing through, but it can't end
+        // its move there, so only free hexes count as somewhere to attack from.
+        let free = battle.hex_map[&hex]
+            .occupying_unit
+            .is_none_or(|other| other == enemy);
+        if free && enter_cost(hex).is_some() {
+            costs.insert(hex, 0);
+            frontier.push(Reverse((0, hex)));
+        }
+    }
+
+    // Searching backwards from the goals: going from `neighbor` to `hex` costs entering `hex`.
+    while let Some(Reverse((cost, hex))) = frontier.pop() {
+        if cost > costs[&hex] {
+            continue; // stale entry
+        }
+        let Some(step) = enter_cost(hex) else {
+            continue;
+        };
+        for neighbor in hex_neighbors(hex, battle.grid_width, battle.grid_height) {
+            if enter_cost(neighbor).is_none() {
+                continue;
+            }
+            let new_cost = cost + step;
+            if costs.get(&neighbor).is_some_and(|&c| c <= new_cost) {
+                continue;
+            }
+            costs.insert(neighbor, new_cost);
+            frontier.push(Reverse((new_cost, neighbor)));
+        }
+    }
+    costs
+}
+
+/// Damage the other side could deal to the unit on `hex` next turn. Opponents already in range
+/// count fully, those that have to walk there count for `WALKING_THREAT` of it.
+fn exposure(battle: &BattleState, enemy: UnitRef, hex: Hex) -> f32 {
+    let mut threat = 0.0;
+    for hero in opponents(battle, enemy) {
+        let (Some(hero_hex), Some(abilities)) =
+            (battle.unit_hex(hero), battle.unit_abilities(hero))
+        else {
+            continue;
+        };
+        let distance = hex_distance(hex, hero_hex);
+        let reach = abilities.iter().map(|a| a.range).max().unwrap_or(1);
+        let share = if distance <= reach {
+            1.0
+        } else if distance <= reach + battle.unit_movement(hero).unwrap_or(0) {
+            WALKING_THREAT
+        } else {
+            continue;
+        };
+        let damage = abilities
+            .iter()
+            .map(|a| battle.expected_damage(hero, enemy, a.damage_modifier))
+            .fold(0.0, f32::max);
+        threat += share * damage;
+    }
+    threat
+}
+
+/// How much the AI likes standing on a terrain. Forest counts as cover and water as poor
+/// footing. Battles don't model either yet, so this only shapes where enemies stand.
+fn terrain_value(terrain: Terrain) -> f32 {
+    match terrain {
+        Terrain::Forest => 1.0,
+        Terrain::Water => -1.0,
+        Terrain::Plain | Terrain::Mountain => 0.0,
+    }
+}
+
+/// The unit's weights adjusted for its behaviour and the difficulty.
+fn ai_weights(battle: &BattleState, unit: UnitRef) -> AiWeights {
+    let mut weights = match unit {
+        UnitRef::Enemy(id) => battle
+            .enemy(id)
+            .map(|e| e.behavior.adjust(&e.ai))
+            .unwrap_or_default(),
+        UnitRef::Hero(_) => AiWeights::default(),
+    };
+    weights.exposure *= battle.difficulty.awareness();
+    weights
+}
+
+/// Hex a guardian keeps close to and how far it may go from it.
+fn guarded_post(battle: &BattleState, unit: UnitRef) -> Option<(Hex, i32)> {
+    let UnitRef::Enemy(id) = unit else {
+        return None;
+    };
+    let enemy = battle.enemy(id)?;
+    match enemy.behavior {
+        AiBehavior::Guardian { radius } => enemy.post.map(|post| (post, radius)),
+        _ => None,
+    }
+}
+
+/// Other units of the same side within `range` hexes of `hex`.
+fn allies_within(battle: &BattleState, unit: UnitRef, hex: Hex, range: i32) -> usize {
+    let hexes: Vec<Hex> = match unit {
+        UnitRef::Hero(_) => battle
+            .heroes
+            .iter()
+            .filter(|(&id, _)| UnitRef::Hero(id) != unit)
+            .map(|(_, h)| h.hex)
+            .collect(),
+        UnitRef::Enemy(_) => battle
+            .enemies
+            .iter()
+            .filter(|(&id, _)| UnitRef::Enemy(id) != unit)
+            .map(|(_, e)| e.hex)
+            .collect(),
+    };
+    hexes
+        .into_iter()
+        .filter(|&other| hex_distance(hex, other) <= range)
+        .count()
+}
+
+/// Units on the other side of the battle, in a fixed order.
+fn opponents(battle: &BattleState, unit: UnitRef) -> Vec<UnitRef> {
+    let mut units: Vec<UnitRef> = match unit {
+        UnitRef::Hero(_) => battle
+            .enemies
+            .keys()
+            .map(|&id| UnitRef::Enemy(id))
+            .collect(),
+        UnitRef::Enemy(_) => battle.heroes.keys().map(|&id| UnitRef::Hero(id)).collect(),
+    };
+    units.sort();
+    units
 }
 
-fn enemy_ai_can_attack_hero(battle: &BattleState, enemy: UnitRef, hero: UnitRef) -> bool {
-    let attack_range = 1; //enemy.attack_range;// Placeholder for enemy attack range
-    let hex = battle.unit_hex(enemy).unwrap();
-    let attack_area = hex.range_to_area(attack_range, battle.grid_width, battle.grid_height);
-    battle.is_unit_in_area(hero, &attack_area)
+fn same_side(a: UnitRef, b: UnitRef) -> bool {
+    matches!(
+        (a, b),
+        (UnitRef::Hero(_), UnitRef::Hero(_)) | (UnitRef::Enemy(_), UnitRef::Enemy(_))
+    )
 }
 
-fn enemy_ai_attack_hero(battle: &mut BattleState, enemy: UnitRef, hero: UnitRef) {
-    battle.attack_unit(enemy, hero, 1.0);
+fn health_share(battle: &BattleState, unit: UnitRef) -> f32 {
+    battle.unit(unit).map_or(1.0, |u| {
+        u.stats().hp as f32 / u.stats().max_hp.max(1) as f32
+    })
+}
+
+fn effect_value(effect: &Effect) -> f32 {
+    match *effect {
+        Effect::Poison(damage, turns) => (damage * turns) as f32,
+        Effect::ReduceMovement(amount, turns) => (amount * turns * 2) as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battlemap::BattleMap;
+    use crate::character::LineOfSight;
+    use crate::testing::*;
+
+    fn ability(name: &str, damage_modifier: f32, effect: Option<Effect>, range: i32) -> Ability {
+        Ability {
+            name: name.to_string(),
+            damage_modifier,
+            effect,
+            range,
+            ..slash()
+        }
+    }
+
+    fn battle_with(abilities: Vec<Ability>, hero_hps: &[i32]) -> BattleState {
+        let heroes: Vec<_> = (0..hero_hps.len())
+            .map(|i| hero(i as u32, stats(hero_hps[i], (8, 16), 10, 2)))
+            .collect();
+        let mut goblin = enemy(0, "Goblin", stats(30, (5, 10), 15, 3));
+        goblin.abilities = abilities;
+        BattleState::new(&heroes, &[goblin], &test_map(), 7).unwrap()
+    }
+
+    /// Ability and target the unit would pick without moving.
+    fn attack_from_here(battle: &BattleState, unit: UnitRef) -> Option<(usize, UnitRef)> {
+        let hex = battle.unit_hex(unit).unwrap();
+        best_attack_from(battle, unit, hex, &ai_weights(battle, unit)).map(|(_, attack)| attack)
+    }
+
+    fn place(battle: &mut BattleState, unit: UnitRef, hex: Hex) {
+        match unit {
+            UnitRef::Hero(id) => battle.heroes.get_mut(&id).unwrap().hex = hex,
+            UnitRef::Enemy(id) => battle.enemies.get_mut(&id).unwrap().hex = hex,
+        }
+    }
+
+    #[test]
+    fn archer_shoots_from_range_without_moving() {
+        let mut battle = battle_with(vec![ability("Shortbow", 0.8, None, 4)], &[100]);
+        let archer = UnitRef::Enemy(0);
+        place(&mut battle, UnitRef::Hero(0), Hex { q: 2, r: 3 });
+        place(&mut battle, archer, Hex { q: 6, r: 3 });
+        assert_eq!(hex_distance(Hex { q: 2, r: 3 }, Hex { q: 6, r: 3 }), 4);
+
+        let decision = enemy_ai(&mut battle, archer);
+
+        assert_eq!(decision.move_to, Hex { q: 6, r: 3 });
+        assert_eq!(decision.ability, Some(0));
+        assert_eq!(decision.target, Some(UnitRef::Hero(0)));
+        assert!(battle.unit_current_health(UnitRef::Hero(0)) < 100);
+    }
+
+    #[test]
+    fn ranged_enemy_stops_once_in_range() {
+        let mut battle = battle_with(vec![ability("Shortbow", 0.8, None, 2)], &[100]);
+        let archer = UnitRef::Enemy(0);
+        place(&mut battle, UnitRef::Hero(0), Hex { q: 2, r: 3 });
+        place(&mut battle, archer, Hex { q: 6, r: 3 });
+        battle.enemies.get_mut(&0).unwrap().current_movement = 3;
+
+        let decision = enemy_ai(&mut battle, archer);
+
+        assert_eq!(hex_distance(decision.move_to, Hex { q: 2, r: 3 }), 2);
+    }
+
+    #[test]
+    fn prefers_killing_blow_then_effects() {
+        let abilities = vec![
+            ability("Stab", 1.0, None, 1),
+            ability("Poisoned Blade", 0.5, Some(Effect::Poison(4, 3)), 1),
+        ];
+        let mut battle = battle_with(abilities, &[100, 5]);
+        let goblin = UnitRef::Enemy(0);
+        place(&mut battle, UnitRef::Hero(0), Hex { q: 2, r: 3 });
+        place(&mut battle, UnitRef::Hero(1), Hex { q: 4, r: 3 });
+        place(&mut battle, goblin, Hex { q: 3, r: 3 });
+
+        assert_eq!(
+            attack_from_here(&battle, goblin),
+            Some((0, UnitRef::Hero(1)))
+        );
+
+        battle.heroes.remove(&1);
+        assert_eq!(
+            attack_from_here(&battle, goblin),
+            Some((1, UnitRef::Hero(0)))
+        );
+    }
+
+    #[test]
+    fn focuses_fire_on_the_wounded_hero() {
+        let mut battle = battle_with(vec![ability("Stab", 1.0, None, 1)], &[100, 100]);
+        let goblin = UnitRef::Enemy(0);
+        place(&mut battle, UnitRef::Hero(0), Hex { q: 2, r: 3 });
+        place(&mut battle, UnitRef::Hero(1), Hex { q: 4, r: 3 });
+        place(&mut battle, goblin, Hex { q: 3, r: 3 });
+        battle.heroes.get_mut(&1).unwrap().stats.hp = 60;
+
+        assert_eq!(
+            attack_from_here(&battle, goblin),
+            Some((0, UnitRef::Hero(1)))
+        );
+    }
+
+    #[test]
+    fn badly_hurt_enemy_retreats_unless_told_to_hold() {
+        let hero_hex = Hex { q: 2, r: 3 };
+        let setup = |retreat_below: f32| {
+            let mut battle = battle_with(vec![ability("Stab", 1.0, None, 1)], &[100]);
+            place(&mut battle, UnitRef::Hero(0), hero_hex);
+            place(&mut battle, UnitRef::Enemy(0), Hex { q: 3, r: 3 });
+            battle.update_occupied_hexes();
+            let goblin = battle.enemies.get_mut(&0).unwrap();
+            goblin.stats.hp = 5;
+            goblin.ai.retreat_below = retreat_below;
+            battle
+        };
+
+        let mut battle = setup(0.25);
+        let decision = enemy_ai(&mut battle, UnitRef::Enemy(0));
+        assert!(hex_distance(decision.move_to, hero_hex) > 1);
+
+        let mut battle = setup(0.0);
+        let decision = enemy_ai(&mut battle, UnitRef::Enemy(0));
+        assert_eq!(hex_distance(decision.move_to, hero_hex), 1);
+        assert_eq!(decision.target, Some(UnitRef::Hero(0)));
+    }
+
+    #[test]
+    fn heroes_only_retreat_when_told_to() {
+        let goblin_hex = Hex { q: 7, r: 3 };
+        let mut battle = battle_with(vec![ability("Stab", 1.0, None, 1)], &[100]);
+        place(&mut battle, UnitRef::Hero(0), Hex { q: 2, r: 3 });
+        place(&mut battle, UnitRef::Enemy(0), goblin_hex);
+        battle.update_occupied_hexes();
+        battle.heroes.get_mut(&0).unwrap().stats.hp = 5;
+
+        let decision = hero_ai(&mut battle, UnitRef::Hero(0), AiBehavior::Standard);
+        assert!(hex_distance(decision.move_to, goblin_hex) < 5);
+
+        let decision = hero_ai(&mut battle, UnitRef::Hero(0), AiBehavior::Coward);
+        assert!(hex_distance(decision.move_to, goblin_hex) > 5);
+    }
+
+    #[test]
+    fn guardian_stays_near_its_post() {
+        let mut battle = battle_with(vec![ability("Stab", 1.0, None, 1)], &[100]);
+        place(&mut battle, UnitRef::Hero(0), Hex { q: 2, r: 3 });
+        place(&mut battle, UnitRef::Enemy(0), Hex { q: 7, r: 3 });
+        battle.update_occupied_hexes();
+        let goblin = battle.enemies.get_mut(&0).unwrap();
+        goblin.behavior = AiBehavior::Guardian { radius: 1 };
+        goblin.post = Some(Hex { q: 7, r: 3 });
+
+        let decision = enemy_ai(&mut battle, UnitRef::Enemy(0));
+
+        assert!(hex_distance(decision.move_to, Hex { q: 7, r: 3 }) <= 1);
+        assert!(hex_distance(decision.move_to, Hex { q: 2, r: 3 }) < 5);
+    }
+
+    #[test]
+    fn difficulty_scales_enemy_stats() {
+        let mut hard = stats(40, (8, 12), 10, 3);
+        let mut easy = hard.clone();
+
+        Difficulty::Hard.scale_stats(&mut hard);
+        Difficulty::Easy.scale_stats(&mut easy);
+
+        assert_eq!((hard.max_hp, hard.hp, hard.damage), (50, 50, (10, 15)));
+        assert_eq!((easy.max_hp, easy.hp, easy.damage), (32, 32, (6, 10)));
+    }
+
+    fn plains_with_wall(wall: bool) -> BattleMap {
+        // With `wall`, mountains run down column 4 except for a gap in the bottom row.
+        let mut terrain = vec![if wall { "....M....." } else { ".........." }.to_string(); 9];
+        terrain.push("..........".to_string());
+        BattleMap {
+            id: "test".to_string(),
+            width: 10,
+            height: 10,
+            terrain,
+            objects: Vec::new(),
+            hero_spawns: vec![Hex { q: 1, r: 1 }],
+            enemy_spawns: vec![Hex { q: 7, r: 1 }, Hex { q: 8, r: 1 }, Hex { q: 9, r: 1 }],
+        }
+    }
+
+    /// Lets every enemy act for a number of rounds while the hero stands still.
+    fn run_enemy_rounds(battle: &mut BattleState, rounds: usize) {
+        for _ in 0..rounds {
+            let mut ids: Vec<_> = battle.enemies.keys().copied().collect();
+            ids.sort();
+            for id in ids {
+                let enemy = battle.enemies.get_mut(&id).unwrap();
+                enemy.current_movement = enemy.stats.movement;
+                enemy_ai(battle, UnitRef::Enemy(id));
+            }
+        }
+    }
+
+    #[test]
+    fn walks_around_a_wall_over_several_turns() {
+        let heroes = [hero(0, stats(1000, (8, 16), 10, 2))];
+        let goblin = enemy(0, "Goblin", stats(30, (5, 10), 15, 3));
+        let mut battle = BattleState::new(&heroes, &[goblin], &plains_with_wall(true), 7).unwrap();
+
+        run_enemy_rounds(&mut battle, 10);
+
+        assert_eq!(hex_distance(battle.enemies[&0].hex, Hex { q: 1, r: 1 }), 1);
+    }
+
+    #[test]
+    fn engages_a_hard_hitting_hero_instead_of_waiting_out_of_reach() {
+        let heroes = [hero(0, stats(100, (20, 30), 10, 2))];
+        let mut goblin = enemy(0, "Goblin", stats(30, (5, 10), 15, 3));
+        goblin.abilities = vec![slash()];
+        let mut battle = BattleState::new(&heroes, &[goblin], &test_map(), 7).unwrap();
+
+        run_enemy_rounds(&mut battle, 4);
+
+        assert!(battle.heroes[&0].stats.hp < 100);
+    }
+
+    #[test]
+    fn melee_enemies_surround_instead_of_queueing() {
+        let heroes = [hero(0, stats(1000, (8, 16), 10, 2))];
+        let goblins: Vec<_> = (0..3)
+            .map(|i| enemy(i, "Goblin", stats(30, (5, 10), 15, 3)))
+            .collect();
+        let mut battle = BattleState::new(&heroes, &goblins, &plains_with_wall(false), 7).unwrap();
+
+        run_enemy_rounds(&mut battle, 6);
+
+        for goblin in battle.enemies.values() {
+            assert_eq!(hex_distance(goblin.hex, Hex { q: 1, r: 1 }), 1);
+        }
+    }
+
+    #[test]
+    fn hexes_held_by_allies_are_not_places_to_attack_from() {
+        let heroes = [hero(0, stats(1000, (8, 16), 10, 2))];
+        let goblins: Vec<_> = (0..2)
+            .map(|i| enemy(i, "Goblin", stats(30, (5, 10), 15, 3)))
+            .collect();
+        let mut battle = BattleState::new(&heroes, &goblins, &plains_with_wall(false), 7).unwrap();
+        place(&mut battle, UnitRef::Enemy(0), Hex { q: 2, r: 1 });
+        place(&mut battle, UnitRef::Enemy(1), Hex { q: 7, r: 1 });
+        battle.update_occupied_hexes();
+
+        let costs = approach_costs(&battle, UnitRef::Enemy(1));
+
+        assert!(costs[&Hex { q: 2, r: 1 }] > 0);
+        assert_eq!(costs[&Hex { q: 1, r: 2 }], 0);
+    }
+
+    #[test]
+    fn archers_need_a_clear_line_past_mountains() {
+        for wall in [false, true] {
+            let heroes = [hero(0, stats(100, (8, 16), 10, 2))];
+            let mut archer = enemy(0, "Goblin Archer", stats(30, (5, 10), 15, 3));
+            archer.abilities = vec![Ability {
+                line_of_sight: LineOfSight::Terrain,
+                ..ability("Shortbow", 0.8, None, 4)
+            }];
+            let mut battle =
+                BattleState::new(&heroes, &[archer], &plains_with_wall(wall), 7).unwrap();
+            place(&mut battle, UnitRef::Enemy(0), Hex { q: 5, r: 1 });
+            battle.update_occupied_hexes();
+
+            let decision = enemy_ai(&mut battle, UnitRef::Enemy(0));
+
+            assert_eq!(decision.ability.is_some(), !wall, "wall: {}", wall);
+        }
+    }
 }
@@ -5,6 +5,8 @@ use std::collections::HashMap;
 pub struct Assets {
     pub hero: Texture2D,
     pub enemy: HashMap<String, Texture2D>,
+    // Loaded for the rock obstacles, which aren't drawn yet.
+    #[allow(dead_code)]
     pub rocks: Texture2D,
 }
 
@@ -13,12 +15,18 @@ impl Assets {
         let hero = load_texture("assets/fighter.png").await.unwrap();
         let goblin = load_texture("assets/goblin.png").await.unwrap();
         let orc = load_texture("assets/orc.png").await.unwrap();
+        let archer = load_texture("assets/archer.png").await.unwrap();
         let rocks = load_texture("assets/rocks.png").await.unwrap();
         hero.set_filter(FilterMode::Nearest);
         goblin.set_filter(FilterMode::Nearest);
         orc.set_filter(FilterMode::Nearest);
+        archer.set_filter(FilterMode::Nearest);
         rocks.set_filter(FilterMode::Nearest);
-        let enemy = HashMap::from([("Goblin".to_string(), goblin), ("Orc".to_string(), orc)]);
+        let enemy = HashMap::from([
+            ("Goblin".to_string(), goblin),
+            ("Orc".to_string(), orc),
+            ("Goblin Archer".to_string(), archer),
+        ]);
         Self { hero, enemy, rocks }
     }
 }
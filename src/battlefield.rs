@@ -1,6 +1,7 @@
 use crate::battlestate::UnitRef;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Terrain {
     Plain,
     Forest,
@@ -8,14 +9,42 @@ pub enum Terrain {
     Water,
 }
 
+impl Terrain {
+    /// Symbol used for the terrain in map files.
+    pub fn from_symbol(symbol: char) -> Option<Terrain> {
+        match symbol {
+            '.' => Some(Terrain::Plain),
+            'F' => Some(Terrain::Forest),
+            'M' => Some(Terrain::Mountain),
+            'W' => Some(Terrain::Water),
+            _ => None,
+        }
+    }
+
+    /// Movement points it costs to enter a hex of this terrain, `None` if it can't be entered.
+    pub fn movement_cost(self) -> Option<i32> {
+        match self {
+            Terrain::Plain => Some(1),
+            Terrain::Forest => Some(2),
+            Terrain::Water => Some(3), // shallow, wading through is slow
+            Terrain::Mountain => None,
+        }
+    }
+
+    /// Whether abilities that need a clear line can't see past a hex of this terrain.
+    pub fn blocks_sight(self) -> bool {
+        matches!(self, Terrain::Mountain)
+    }
+}
+
 // Placeholder for later
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Object {
     HealingShrine,
     Trap,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HexInfo {
     pub terrain: Terrain,
     pub passable: bool,
@@ -25,7 +54,7 @@ pub struct HexInfo {
 
 impl HexInfo {
     pub fn new(terrain: Terrain) -> Self {
-        let passable = matches!(terrain, Terrain::Plain | Terrain::Forest);
+        let passable = terrain.movement_cost().is_some();
         Self {
             terrain,
             passable,
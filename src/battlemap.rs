@@ -0,0 +1,182 @@
+use crate::battlefield::{HexInfo, Object, Terrain};
+use crate::hexgrid::Hex;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+pub const MAPS_DIR: &str = "data/maps";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlacedObject {
+    pub hex: Hex,
+    pub object: Object,
+}
+
+/// Battle map as stored in `data/maps/<id>.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BattleMap {
+    pub id: String,
+    pub width: i32,
+    pub height: i32,
+    /// One string per row (`r`), one symbol per hex (`q`). See `Terrain::from_symbol`.
+    pub terrain: Vec<String>,
+    #[serde(default)]
+    pub objects: Vec<PlacedObject>,
+    pub hero_spawns: Vec<Hex>,
+    pub enemy_spawns: Vec<Hex>,
+}
+
+impl BattleMap {
+    pub fn load(id: &str) -> anyhow::Result<Self> {
+        Self::load_from(MAPS_DIR, id)
+    }
+
+    pub fn load_from(dir: impl AsRef<Path>, id: &str) -> anyhow::Result<Self> {
+        let path = dir.as_ref().join(format!("{}.json", id));
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading map {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("loading map {}", path.display()))
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let map: BattleMap = serde_json::from_str(json)?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.width <= 0 || self.height <= 0 {
+            bail!("map size {}x{} is empty", self.width, self.height);
+        }
+        if self.terrain.len() != self.height as usize {
+            bail!(
+                "expected {} terrain rows, found {}",
+                self.height,
+                self.terrain.len()
+            );
+        }
+        for (r, row) in self.terrain.iter().enumerate() {
+            if row.chars().count() != self.width as usize {
+                bail!("terrain row {} should have {} hexes", r, self.width);
+            }
+            if let Some(c) = row.chars().find(|&c| Terrain::from_symbol(c).is_none()) {
+                bail!("unknown terrain symbol '{}' in row {}", c, r);
+            }
+        }
+
+        for obj in &self.objects {
+            if !self.contains(obj.hex) {
+                bail!("{:?} placed outside the map at {:?}", obj.object, obj.hex);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for (side, spawns) in [("hero", &self.hero_spawns), ("enemy", &self.enemy_spawns)] {
+            if spawns.is_empty() {
+                bail!("map has no {} spawns", side);
+            }
+            for &hex in spawns {
+                if !self.contains(hex) {
+                    bail!("{} spawn {:?} is outside the map", side, hex);
+                }
+                if self.terrain_at(hex).movement_cost().is_none() {
+                    bail!("{} spawn {:?} is on impassable terrain", side, hex);
+                }
+                if !seen.insert(hex) {
+                    bail!("spawn {:?} is listed twice", hex);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn contains(&self, hex: Hex) -> bool {
+        (0..self.width).contains(&hex.q) && (0..self.height).contains(&hex.r)
+    }
+
+    /// Terrain of a hex inside a validated map.
+    pub fn terrain_at(&self, hex: Hex) -> Terrain {
+        let symbol = self.terrain[hex.r as usize]
+            .chars()
+            .nth(hex.q as usize)
+            .unwrap();
+        Terrain::from_symbol(symbol).unwrap()
+    }
+
+    pub fn hex_infos(&self) -> HashMap<Hex, HexInfo> {
+        let mut map = HashMap::new();
+        for q in 0..self.width {
+            for r in 0..self.height {
+                let hex = Hex { q, r };
+                map.insert(hex, HexInfo::new(self.terrain_at(hex)));
+            }
+        }
+        for obj in &self.objects {
+            map.get_mut(&obj.hex)
+                .unwrap()
+                .objects
+                .push(obj.object.clone());
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: &str = r#"{
+        "id": "small",
+        "width": 3,
+        "height": 2,
+        "terrain": [".FM", "W.."],
+        "objects": [{ "hex": { "q": 1, "r": 1 }, "object": "Trap" }],
+        "hero_spawns": [{ "q": 0, "r": 0 }],
+        "enemy_spawns": [{ "q": 2, "r": 1 }]
+    }"#;
+
+    #[test]
+    fn parses_terrain_and_objects() {
+        let map = BattleMap::from_json(SMALL).unwrap();
+        let hexes = map.hex_infos();
+
+        assert_eq!(hexes.len(), 6);
+        assert_eq!(hexes[&Hex { q: 1, r: 0 }].terrain, Terrain::Forest);
+        assert_eq!(hexes[&Hex { q: 2, r: 0 }].terrain, Terrain::Mountain);
+        assert_eq!(hexes[&Hex { q: 0, r: 1 }].terrain, Terrain::Water);
+        assert_eq!(hexes[&Hex { q: 1, r: 1 }].objects.len(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_maps() {
+        let cases = [
+            SMALL.replace(r#"".FM""#, r#"".F""#),
+            SMALL.replace(r#"".FM""#, r#"".F?""#),
+            SMALL.replace(r#""height": 2"#, r#""height": 3"#),
+            SMALL.replace(
+                r#""hero_spawns": [{ "q": 0, "r": 0 }]"#,
+                r#""hero_spawns": [{ "q": 2, "r": 0 }]"#,
+            ),
+            SMALL.replace(
+                r#""hero_spawns": [{ "q": 0, "r": 0 }]"#,
+                r#""hero_spawns": []"#,
+            ),
+            SMALL.replace(r#""object": "Trap""#, r#""object": "Dragon""#),
+        ];
+
+        for json in cases {
+            assert!(BattleMap::from_json(&json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn shipped_maps_are_valid() {
+        for entry in std::fs::read_dir(MAPS_DIR).unwrap() {
+            let path = entry.unwrap().path();
+            let id = path.file_stem().unwrap().to_str().unwrap();
+            BattleMap::load(id).unwrap();
+        }
+    }
+}
@@ -1,52 +1,61 @@
-use crate::ai::enemy_ai;
-use crate::assets::Assets;
-use crate::battlefield::{HexInfo, Terrain};
-use crate::character::{Ability, Enemy, Hero, Stats};
+use crate::ai::{enemy_ai, AiBehavior, AiWeights, Difficulty};
+use crate::battlefield::HexInfo;
+use crate::battlemap::BattleMap;
+use crate::character::{Ability, Enemy, Hero, LineOfSight, Stats};
 use crate::effect::Effect;
-use crate::hexgrid::Hex;
+use crate::hexgrid::{hex_keyed, Hex};
+use crate::item::{Consumable, Item};
+use crate::loot::{Loot, LootDrop, LootEntry};
 use crate::pathfinding::movement_range;
-use ::rand::{thread_rng, Rng};
-use macroquad::prelude::*;
+use crate::replay::{BattleLog, BattleSetup, LogEntry};
+use anyhow::bail;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum BattleResult {
     Victory,
     Defeat,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum UnitRef {
     Hero(u32),
     Enemy(u32),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InputMode {
     Normal,
     Movement,
     AbilityTarget(usize),
+    ItemTarget(usize),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TurnPhase {
     Start,
     Action,
     End,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BattleCommand {
     SelectUnit(UnitRef),
     SelectUnitAtHex(Hex),
     MoveSelectedUnit(Hex),
     SelectAbility(usize),
     UseAbility(Hex),
+    SelectItem(usize),
+    UseItem(Hex),
     CancelAction,
     RequestEndTurn,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HeroInstance {
     pub id: u32,
     pub name: String,
@@ -58,38 +67,52 @@ pub struct HeroInstance {
     pub current_movement: i32,
     pub action_available: bool,
     pub effects: Vec<Effect>,
-
-    pub texture: Texture2D,
+    pub on_hit_effects: Vec<Effect>,
+    /// Consumables brought from the backpack.
+    pub items: Vec<Item>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EnemyInstance {
     pub id: u32,
     pub name: String,
     pub hex: Hex,
 
     pub stats: Stats,
-    // pub abilities: Vec<Ability>,
+    pub abilities: Vec<Ability>,
     pub current_movement: i32,
     // pub action_available: bool,
     pub effects: Vec<Effect>,
-
-    pub texture: Texture2D,
+    pub xp_reward: u64,
+    pub loot: Vec<LootEntry>,
+    #[serde(default)]
+    pub ai: AiWeights,
+    #[serde(default)]
+    pub behavior: AiBehavior,
+    /// Hex the enemy started on, which guardians keep close to.
+    #[serde(default)]
+    pub post: Option<Hex>,
 }
 
+/// Read-only view of a unit for the UI. Sprites are looked up by the UI from `unit_ref` and `name`.
 pub trait UnitRender {
+    fn unit_ref(&self) -> UnitRef;
+    fn name(&self) -> &str;
     fn hex(&self) -> Hex;
-    fn texture(&self) -> &Texture2D;
     fn health_percent(&self) -> f32;
 }
 
 impl UnitRender for HeroInstance {
-    fn hex(&self) -> Hex {
-        self.hex
+    fn unit_ref(&self) -> UnitRef {
+        UnitRef::Hero(self.id)
     }
 
-    fn texture(&self) -> &Texture2D {
-        &self.texture
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn hex(&self) -> Hex {
+        self.hex
     }
 
     fn health_percent(&self) -> f32 {
@@ -98,12 +121,16 @@ impl UnitRender for HeroInstance {
 }
 
 impl UnitRender for EnemyInstance {
-    fn hex(&self) -> Hex {
-        self.hex
+    fn unit_ref(&self) -> UnitRef {
+        UnitRef::Enemy(self.id)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
     }
 
-    fn texture(&self) -> &Texture2D {
-        &self.texture
+    fn hex(&self) -> Hex {
+        self.hex
     }
 
     fn health_percent(&self) -> f32 {
@@ -111,6 +138,9 @@ impl UnitRender for EnemyInstance {
     }
 }
 
+/// Full battle state. It serializes completely, RNG included, so a saved battle resumes with
+/// the same rolls coming next.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BattleState {
     pub heroes: HashMap<u32, HeroInstance>,
     pub enemies: HashMap<u32, EnemyInstance>,
@@ -119,48 +149,88 @@ pub struct BattleState {
     pub active_unit_idx: usize,
 
     pub selected_unit: Option<UnitRef>,
+    #[serde(with = "hex_keyed")]
     pub selected_unit_range: HashMap<Hex, (i32, Vec<Hex>)>,
     pub selected_ability: Option<usize>,
+    pub selected_item: Option<usize>,
+    /// Hexes the selected ability or item can target.
     pub selected_ability_range: Vec<Hex>,
+    /// Hexes in range of the selected ability that it has no clear line to.
+    #[serde(default)]
+    pub selected_ability_blocked: Vec<Hex>,
 
     pub grid_width: i32,
     pub grid_height: i32,
+    #[serde(with = "hex_keyed")]
     pub hex_map: HashMap<Hex, HexInfo>,
 
     pub phase: TurnPhase,
     pub input_mode: InputMode,
 
     pub result: Option<BattleResult>,
+    /// XP from every enemy defeated so far, handed out to the survivors after a victory.
+    pub xp_earned: u64,
+    /// Dropped by defeated enemies, rolled with the battle RNG when they die.
+    pub loot: Loot,
+    /// Ids of items used up during the battle, removed from backpacks afterwards.
+    pub consumed_items: Vec<u64>,
+
+    /// How well the enemies play. Enemy stats are already scaled for it when the battle starts.
+    #[serde(default)]
+    pub difficulty: Difficulty,
+
+    /// Seed the battle RNG was created from. Same seed and same commands give the same battle.
+    pub seed: u64,
+    rng: ChaCha8Rng,
+
+    /// Every accepted command and AI decision, enough to replay the battle.
+    pub log: BattleLog,
 }
 
 impl HeroInstance {
-    pub fn from_hero(hero: &Hero, hex: Hex, texture: Texture2D) -> Self {
+    pub fn from_hero(hero: &Hero, hex: Hex) -> Self {
+        let stats = hero.effective_stats();
         Self {
             id: hero.id,
             name: hero.name.clone(),
             hex,
-            stats: hero.stats.clone(),
+            current_movement: stats.movement,
+            stats,
             abilities: hero.abilities.clone(),
-            current_movement: hero.stats.movement,
             action_available: true,
             effects: vec![],
-            texture,
+            on_hit_effects: hero.on_hit_effects(),
+            items: hero
+                .inventory
+                .backpack
+                .iter()
+                .filter(|item| item.consumable.is_some())
+                .cloned()
+                .collect(),
         }
     }
 }
 
 impl EnemyInstance {
-    pub fn from_enemy(enemy: &Enemy, hex: Hex, texture: Texture2D) -> Self {
+    pub fn from_enemy(enemy: &Enemy, hex: Hex) -> Self {
         Self {
             id: enemy.id,
             name: enemy.name.clone(),
             hex,
             stats: enemy.stats.clone(),
-            // abilities: enemy.abilities.clone(),
+            abilities: if enemy.abilities.is_empty() {
+                vec![Ability::basic_attack()]
+            } else {
+                enemy.abilities.clone()
+            },
             current_movement: enemy.stats.movement,
             // action_available: true,
             effects: vec![],
-            texture,
+            xp_reward: enemy.xp_reward,
+            loot: enemy.loot.clone(),
+            ai: enemy.ai.clone(),
+            behavior: enemy.behavior,
+            post: Some(hex),
         }
     }
 }
@@ -185,6 +255,7 @@ impl BattleState {
         self.heroes.get(&id)
     }
 
+    #[allow(dead_code)]
     pub fn hero_mut(&mut self, id: u32) -> Option<&mut HeroInstance> {
         self.heroes.get_mut(&id)
     }
@@ -193,6 +264,7 @@ impl BattleState {
         self.enemies.get(&id)
     }
 
+    #[allow(dead_code)]
     pub fn enemy_mut(&mut self, id: u32) -> Option<&mut EnemyInstance> {
         self.enemies.get_mut(&id)
     }
@@ -207,6 +279,7 @@ impl BattleState {
         }
     }
 
+    #[allow(dead_code)]
     pub fn unit_current_health_mut(&mut self, u: UnitRef) -> Option<&mut i32> {
         match u {
             UnitRef::Hero(id) => self.heroes.get_mut(&id).map(|h| &mut h.stats.hp),
@@ -221,6 +294,7 @@ impl BattleState {
         }
     }
 
+    #[allow(dead_code)]
     pub fn unit_current_movement_mut(&mut self, u: UnitRef) -> Option<&mut i32> {
         match u {
             UnitRef::Hero(id) => self.heroes.get_mut(&id).map(|h| &mut h.current_movement),
@@ -252,14 +326,14 @@ impl BattleState {
     pub fn unit_action_available(&self, u: UnitRef) -> Option<bool> {
         match u {
             UnitRef::Hero(id) => self.heroes.get(&id).map(|h| h.action_available),
-            UnitRef::Enemy(id) => Some(false),
+            UnitRef::Enemy(_) => Some(false),
         }
     }
 
     pub fn unit_abilities(&self, u: UnitRef) -> Option<&[Ability]> {
         match u {
             UnitRef::Hero(id) => self.heroes.get(&id).map(|h| h.abilities.as_slice()),
-            UnitRef::Enemy(id) => None,
+            UnitRef::Enemy(id) => self.enemies.get(&id).map(|e| e.abilities.as_slice()),
         }
     }
 
@@ -316,25 +390,16 @@ impl BattleState {
             None => return,
         };
 
+        let base_damage = self.roll(attacker_stats.damage.0, attacker_stats.damage.1) as f32;
+
         let target_stats = match self.unit_stats_mut(target) {
             Some(s) => s,
             None => return,
         };
 
-        let attack_vs_defense = attacker_stats.attack - target_stats.defense;
-
-        let attack_modifier = if attack_vs_defense >= 0 {
-            1.0 + ((attack_vs_defense as f32) * 0.05).min(2.0)
-        } else {
-            (1.0 + (attack_vs_defense as f32) * 0.05).max(0.3)
-        };
+        let attack_modifier = attack_modifier(attacker_stats.attack, target_stats.defense);
 
-        let mut rng = thread_rng();
-        let base_damage = rng.gen_range(attacker_stats.damage.0..=attacker_stats.damage.1) as f32;
-
-        let damage = (base_damage * attack_modifier * damage_multiplier)
-            .round()
-            .max(0.0) as i32;
+        let damage = hit_damage(base_damage, attack_modifier, damage_multiplier);
 
         target_stats.hp = (target_stats.hp - damage).max(0);
 
@@ -350,11 +415,111 @@ impl BattleState {
         }
     }
 
-    fn kill_unit(&mut self, unit: UnitRef) {
-        println!("{} has been defeated!", self.unit_name(unit).unwrap());
+    pub fn use_consumable(&mut self, consumable: &Consumable, target: UnitRef) {
+        match consumable {
+            Consumable::Heal(amount) => {
+                if let Some(stats) = self.unit_stats_mut(target) {
+                    stats.hp = (stats.hp + amount).min(stats.max_hp);
+                }
+            }
+            Consumable::Antidote => {
+                if let Some(unit) = self.unit_mut(target) {
+                    unit.effects_mut()
+                        .retain(|e| !matches!(e, Effect::Poison(..)));
+                }
+            }
+            Consumable::Throwable { damage, effect, .. } => {
+                let Some(stats) = self.unit_stats_mut(target) else {
+                    return;
+                };
+                stats.hp = (stats.hp - damage).max(0);
+                if stats.hp == 0 {
+                    self.kill_unit(target);
+                    return;
+                }
+                if let Some(e) = effect {
+                    self.unit_mut(target).unwrap().effects_mut().push(e.clone());
+                }
+            }
+        }
+    }
 
-        if self.active_unit() == unit {
-            self.active_unit_idx -= 1;
+    /// Average damage an attack would deal, used by the AI to compare options.
+    pub fn expected_damage(
+        &self,
+        attacker: UnitRef,
+        target: UnitRef,
+        damage_multiplier: f32,
+    ) -> f32 {
+        let (attacker_stats, target_stats) =
+            match (self.unit_stats(attacker), self.unit_stats(target)) {
+                (Some(a), Some(t)) => (a, t),
+                _ => return 0.0,
+            };
+        let average = (attacker_stats.damage.0 + attacker_stats.damage.1) as f32 / 2.0;
+        average * attack_modifier(attacker_stats.attack, target_stats.defense) * damage_multiplier
+    }
+
+    /// Share of the attacker's damage rolls that would bring the target down in one hit.
+    pub fn kill_chance(&self, attacker: UnitRef, target: UnitRef, damage_multiplier: f32) -> f32 {
+        let (attacker_stats, target_stats) =
+            match (self.unit_stats(attacker), self.unit_stats(target)) {
+                (Some(a), Some(t)) => (a, t),
+                _ => return 0.0,
+            };
+        let modifier = attack_modifier(attacker_stats.attack, target_stats.defense);
+        let (low, high) = (
+            attacker_stats.damage.0,
+            attacker_stats.damage.1.max(attacker_stats.damage.0),
+        );
+        let kills = (low..=high)
+            .filter(|&roll| hit_damage(roll as f32, modifier, damage_multiplier) >= target_stats.hp)
+            .count();
+        kills as f32 / (high - low + 1) as f32
+    }
+
+    /// Hits the target with one of the caster's abilities and applies its effect.
+    pub fn use_ability(&mut self, caster: UnitRef, ability_idx: usize, target: UnitRef) {
+        let ability = match self
+            .unit_abilities(caster)
+            .and_then(|abilities| abilities.get(ability_idx))
+        {
+            Some(a) => a.clone(),
+            None => return,
+        };
+
+        self.attack_unit(caster, target, ability.damage_modifier);
+
+        let mut effects: Vec<Effect> = ability.effect.into_iter().collect();
+        if let Some(caster_unit) = self.unit(caster) {
+            effects.extend_from_slice(caster_unit.on_hit_effects());
+        }
+        if effects.is_empty() {
+            return;
+        }
+
+        if let Some(targetted_unit) = self.unit_mut(target) {
+            targetted_unit.effects_mut().extend(effects);
+        } else {
+            eprintln!("Target died, effect skipped");
+        }
+    }
+
+    fn kill_unit(&mut self, unit: UnitRef) {
+        eprintln!("{} has been defeated!", self.unit_name(unit).unwrap());
+
+        // Keep `active_unit_idx` pointing at the active unit, or at the one right before the
+        // next unit in line if the active unit itself died, so `next_unit` advances correctly.
+        if let Some(pos) = self.turn_order.iter().position(|u| *u == unit) {
+            if pos < self.active_unit_idx {
+                self.active_unit_idx -= 1;
+            } else if pos == self.active_unit_idx {
+                self.active_unit_idx = if pos == 0 {
+                    self.turn_order.len().saturating_sub(2)
+                } else {
+                    pos - 1
+                };
+            }
         }
 
         match unit {
@@ -362,7 +527,10 @@ impl BattleState {
                 self.heroes.remove(&id);
             }
             UnitRef::Enemy(id) => {
-                self.enemies.remove(&id);
+                if let Some(enemy) = self.enemies.remove(&id) {
+                    self.xp_earned += enemy.xp_reward;
+                    self.roll_loot(&enemy.loot);
+                }
             }
         }
 
@@ -378,34 +546,24 @@ impl BattleState {
     }
 }
 
-// --------------------- Hex map management ---------------------
-impl BattleState {
-    pub fn initialize_hex_map(&mut self) {
-        self.hex_map.clear();
-        for q in 0..self.grid_width {
-            for r in 0..self.grid_height {
-                let terrain = if (q + r) % 5 == 0 {
-                    Terrain::Forest
-                } else {
-                    Terrain::Plain
-                }; //Placeholder
-                self.hex_map.insert(Hex { q, r }, HexInfo::new(terrain));
-            }
-        }
-        let mountain_hexes = Vec::from([
-            Hex { q: 3, r: 4 },
-            Hex { q: 4, r: 4 },
-            Hex { q: 5, r: 2 },
-            Hex { q: 8, r: 2 },
-            Hex { q: 9, r: 4 },
-        ]);
+fn hit_damage(base_damage: f32, attack_modifier: f32, damage_multiplier: f32) -> i32 {
+    (base_damage * attack_modifier * damage_multiplier)
+        .round()
+        .max(0.0) as i32
+}
 
-        for mountain_hex in mountain_hexes {
-            self.hex_map
-                .insert(mountain_hex, HexInfo::new(Terrain::Mountain));
-        }
+fn attack_modifier(attack: i32, defense: i32) -> f32 {
+    let attack_vs_defense = attack - defense;
+
+    if attack_vs_defense >= 0 {
+        1.0 + ((attack_vs_defense as f32) * 0.05).min(2.0)
+    } else {
+        (1.0 + (attack_vs_defense as f32) * 0.05).max(0.3)
     }
+}
 
+// --------------------- Hex map management ---------------------
+impl BattleState {
     pub fn update_occupied_hexes(&mut self) {
         for info in self.hex_map.values_mut() {
             info.occupying_unit = None;
@@ -428,26 +586,37 @@ impl BattleState {
         }
     }
 
+    #[allow(dead_code)]
     pub fn is_hex_passable(&self, hex: Hex) -> bool {
         self.hex_map
             .get(&hex)
-            .map_or(false, |info| info.passable && !info.is_occupied())
+            .is_some_and(|info| info.passable && !info.is_occupied())
     }
 
+    /// Movement points needed to enter the hex, `None` if it is impassable or occupied.
+    pub fn movement_cost(&self, hex: Hex) -> Option<i32> {
+        let info = self.hex_map.get(&hex)?;
+        if !info.passable || info.is_occupied() {
+            return None;
+        }
+        info.terrain.movement_cost()
+    }
+
+    #[allow(dead_code)]
     pub fn units_in_area(&self, unit: UnitRef, area: Vec<Hex>) -> (Vec<UnitRef>, Vec<UnitRef>) {
-        let in_range = |hex: &Hex| area.iter().any(|h| h == hex);
+        let in_range = |hex: &Hex| area.contains(hex);
 
         let heroes: Vec<UnitRef> = self
             .heroes
             .iter()
-            .filter(|(i, hero)| in_range(&hero.hex))
+            .filter(|(_, hero)| in_range(&hero.hex))
             .map(|(i, _)| UnitRef::Hero(*i))
             .collect();
 
         let enemies: Vec<UnitRef> = self
             .enemies
             .iter()
-            .filter(|(i, enemy)| in_range(&enemy.hex))
+            .filter(|(_, enemy)| in_range(&enemy.hex))
             .map(|(i, _)| UnitRef::Enemy(*i))
             .collect();
 
@@ -457,9 +626,110 @@ impl BattleState {
         }
     }
 
+    #[allow(dead_code)]
     pub fn is_unit_in_area(&self, unit: UnitRef, area: &[Hex]) -> bool {
         let unit_hex = self.unit_hex(unit).unwrap();
-        area.iter().any(|&h| h == unit_hex)
+        area.contains(&unit_hex)
+    }
+
+    /// Whether `caster` standing on `from` has the line to `to` that `sight` asks for. The
+    /// caster never blocks its own line, so this also works for hexes it could move to.
+    pub fn has_line_of_sight(
+        &self,
+        caster: UnitRef,
+        from: Hex,
+        to: Hex,
+        sight: LineOfSight,
+    ) -> bool {
+        if sight == LineOfSight::Ignore {
+            return true;
+        }
+        from.has_line_of_sight(to, |hex| {
+            let Some(info) = self.hex_map.get(&hex) else {
+                return true;
+            };
+            info.terrain.blocks_sight()
+                || (sight == LineOfSight::TerrainAndUnits
+                    && info.occupying_unit.is_some_and(|u| u != caster))
+        })
+    }
+
+    /// Hexes in range of the ability from `from`, split into those it can target and those it
+    /// has no clear line to.
+    pub fn ability_area(
+        &self,
+        caster: UnitRef,
+        ability: &Ability,
+        from: Hex,
+    ) -> (Vec<Hex>, Vec<Hex>) {
+        from.range_to_area(ability.range, self.grid_width, self.grid_height)
+            .into_iter()
+            .partition(|&hex| self.has_line_of_sight(caster, from, hex, ability.line_of_sight))
+    }
+}
+
+// --------------------- Status effects ---------------------
+impl BattleState {
+    /// Applies the unit's status effects at the start of its turn, counts their durations
+    /// down and drops the expired ones. Returns the movement penalty for this turn.
+    fn apply_status_effects(&mut self, unit: UnitRef) -> i32 {
+        let target = match self.unit_mut(unit) {
+            Some(t) => t,
+            None => return 0,
+        };
+
+        let mut damage = 0;
+        let mut movement_penalty = 0;
+
+        for effect in target.effects_mut().iter_mut() {
+            match *effect {
+                Effect::Poison(dmg, _) => damage += dmg,
+                Effect::ReduceMovement(amount, _) => movement_penalty += amount,
+            }
+            effect.tick();
+        }
+        target.effects_mut().retain(|e| !e.is_expired());
+
+        if damage > 0 {
+            let stats = target.stats_mut();
+            stats.hp = (stats.hp - damage).max(0);
+            eprintln!(
+                "{} takes {} damage from effects",
+                self.unit_name(unit).unwrap(),
+                damage
+            );
+        }
+
+        movement_penalty
+    }
+}
+
+// --------------------- Randomness ---------------------
+impl BattleState {
+    /// Rolls a value in `low..=high` using the battle RNG.
+    pub fn roll(&mut self, low: i32, high: i32) -> i32 {
+        self.rng.gen_range(low..=high.max(low))
+    }
+
+    fn roll_loot(&mut self, table: &[LootEntry]) {
+        for entry in table {
+            if !self.rng.gen_bool(entry.chance.clamp(0.0, 1.0)) {
+                continue;
+            }
+            match &entry.drop {
+                LootDrop::Item(id) => self.loot.items.push(id.clone()),
+                LootDrop::Material { id, min, max } => {
+                    let amount = self.rng.gen_range(*min..=(*max).max(*min));
+                    *self.loot.materials.entry(id.clone()).or_insert(0) += amount;
+                }
+            }
+        }
+    }
+
+    /// Picks one of the candidates using the battle RNG. Candidates should come in a
+    /// deterministic order, not straight from a `HashMap`.
+    pub fn choose<T: Copy>(&mut self, candidates: &[T]) -> Option<T> {
+        candidates.choose(&mut self.rng).copied()
     }
 }
 
@@ -477,6 +747,9 @@ impl BattleState {
             )
             .collect();
 
+        // Units with equal initiative are ordered randomly, but reproducibly for a given seed.
+        units.sort_by_key(|&(_, u)| u);
+        units.shuffle(&mut self.rng);
         units.sort_by_key(|&(dex, _)| std::cmp::Reverse(dex));
 
         self.turn_order = units.into_iter().map(|(_, u)| u).collect();
@@ -515,7 +788,16 @@ impl BattleState {
 
     fn start_phase(&mut self) {
         let unit = self.active_unit();
-        let movement = self.unit_movement(unit).unwrap();
+        let movement_penalty = self.apply_status_effects(unit);
+
+        if self.unit_current_health(unit) == 0 {
+            // Died from damage over time, the turn is skipped.
+            self.kill_unit(unit);
+            self.phase = TurnPhase::End;
+            return;
+        }
+
+        let movement = (self.unit_movement(unit).unwrap() - movement_penalty).max(0);
 
         match unit {
             UnitRef::Hero(id) => {
@@ -549,9 +831,8 @@ impl BattleState {
             }
 
             UnitRef::Enemy(i) => {
-                print!("ACTIVE ENEMY {}", self.active_unit_idx);
-
-                enemy_ai(self, UnitRef::Enemy(i));
+                let decision = enemy_ai(self, UnitRef::Enemy(i));
+                self.log.entries.push(LogEntry::Ai(decision));
                 self.phase = TurnPhase::End;
             }
         }
@@ -585,42 +866,55 @@ impl BattleState {
 }
 
 impl BattleState {
-    pub fn new(heroes: &[Hero], enemies: &Vec<Enemy>, assets: Assets) -> Self {
-        let heroes = heroes
+    pub fn new(
+        heroes: &[Hero],
+        enemies: &[Enemy],
+        map: &BattleMap,
+        seed: u64,
+    ) -> anyhow::Result<Self> {
+        if heroes.len() > map.hero_spawns.len() {
+            bail!(
+                "map {} has {} hero spawns for {} heroes",
+                map.id,
+                map.hero_spawns.len(),
+                heroes.len()
+            );
+        }
+        if enemies.len() > map.enemy_spawns.len() {
+            bail!(
+                "map {} has {} enemy spawns for {} enemies",
+                map.id,
+                map.enemy_spawns.len(),
+                enemies.len()
+            );
+        }
+
+        let setup = BattleSetup {
+            heroes: heroes.to_vec(),
+            enemies: enemies.to_vec(),
+            map: map.clone(),
+            seed,
+            difficulty: Difficulty::default(),
+        };
+
+        // Units are keyed by their own id, which is also what their `UnitRef` carries.
+        let heroes: HashMap<u32, HeroInstance> = heroes
             .iter()
-            .enumerate()
-            .map(|(i, h)| {
-                (
-                    i as u32,
-                    HeroInstance::from_hero(
-                        h,
-                        Hex {
-                            q: 2 + i as i32,
-                            r: 3,
-                        },
-                        assets.hero.clone(),
-                    ),
-                )
-            })
+            .zip(&map.hero_spawns)
+            .map(|(h, &hex)| (h.id, HeroInstance::from_hero(h, hex)))
             .collect();
+        if heroes.len() < setup.heroes.len() {
+            bail!("two heroes share an id");
+        }
 
-        let enemies = enemies
-            .into_iter()
-            .enumerate()
-            .map(|(i, e)| {
-                (
-                    i as u32,
-                    EnemyInstance::from_enemy(
-                        e,
-                        Hex {
-                            q: 7 + i as i32,
-                            r: 5,
-                        },
-                        assets.enemy[&e.name].clone(),
-                    ),
-                )
-            })
+        let enemies: HashMap<u32, EnemyInstance> = enemies
+            .iter()
+            .zip(&map.enemy_spawns)
+            .map(|(e, &hex)| (e.id, EnemyInstance::from_enemy(e, hex)))
             .collect();
+        if enemies.len() < setup.enemies.len() {
+            bail!("two enemies share an id");
+        }
 
         let mut battle = Self {
             heroes,
@@ -632,23 +926,56 @@ impl BattleState {
             selected_unit: None,
             selected_unit_range: HashMap::new(),
             selected_ability: None,
+            selected_item: None,
             selected_ability_range: Vec::new(),
-            grid_width: 10,
-            grid_height: 10,
-            hex_map: HashMap::new(),
+            selected_ability_blocked: Vec::new(),
+            grid_width: map.width,
+            grid_height: map.height,
+            hex_map: map.hex_infos(),
             result: None,
+            xp_earned: 0,
+            loot: Loot::default(),
+            consumed_items: Vec::new(),
+            difficulty: Difficulty::default(),
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            log: BattleLog::new(setup),
         };
 
-        battle.initialize_hex_map();
         battle.update_occupied_hexes();
         battle.generate_turn_order();
 
-        battle
+        Ok(battle)
+    }
+
+    pub fn from_setup(setup: &BattleSetup) -> anyhow::Result<Self> {
+        Ok(
+            Self::new(&setup.heroes, &setup.enemies, &setup.map, setup.seed)?
+                .with_difficulty(setup.difficulty),
+        )
+    }
+
+    pub fn with_difficulty(mut self, difficulty: Difficulty) -> Self {
+        self.difficulty = difficulty;
+        self.log.setup.difficulty = difficulty;
+        self
     }
 }
 
 impl BattleState {
+    /// True while the active hero waits for player commands.
+    pub fn awaiting_input(&self) -> bool {
+        self.result.is_none() && self.phase == TurnPhase::Action && self.is_player_turn()
+    }
+
     pub fn handle_command(&mut self, cmd: BattleCommand) {
+        // Commands outside of the player's action phase are ignored, so that replaying the
+        // log between ticks reproduces the battle exactly.
+        if !self.awaiting_input() {
+            return;
+        }
+        self.log.entries.push(LogEntry::Command(cmd.clone()));
+
         match cmd {
             BattleCommand::SelectUnit(unit) => {
                 self.select_unit(unit);
@@ -670,6 +997,14 @@ impl BattleState {
                 self.try_use_ability(target);
             }
 
+            BattleCommand::SelectItem(item_idx) => {
+                self.select_item(item_idx);
+            }
+
+            BattleCommand::UseItem(target) => {
+                self.try_use_item(target);
+            }
+
             BattleCommand::CancelAction => {
                 self.clear_ability_selection();
             }
@@ -689,17 +1024,15 @@ impl BattleState {
         if let Some(unit) = self.selected_unit {
             let hex = self.unit_hex(unit).unwrap();
             let movement = self.unit_current_movement(unit).unwrap();
-            self.selected_unit_range = movement_range(hex, movement, self);
+            let full_movement = self.unit_movement(unit).unwrap();
+            self.selected_unit_range = movement_range(hex, movement, full_movement, self);
         }
     }
 
     pub fn try_select_unit_at_hex(&mut self, hex: Hex) {
-        match self.hex_map[&hex].occupying_unit {
-            Some(unit) => {
-                self.select_unit(unit);
-                self.input_mode = InputMode::Movement;
-            }
-            None => {}
+        if let Some(unit) = self.hex_map[&hex].occupying_unit {
+            self.select_unit(unit);
+            self.input_mode = InputMode::Movement;
         }
     }
 
@@ -712,22 +1045,35 @@ impl BattleState {
 
         let starting_hex = self.unit_hex(active_unit).unwrap();
         let active_unit_movement = self.unit_current_movement(active_unit).unwrap();
+        let full_movement = self.unit_movement(active_unit).unwrap();
         let reachable: HashMap<Hex, (i32, Vec<Hex>)> =
-            movement_range(starting_hex, active_unit_movement, self);
+            movement_range(starting_hex, active_unit_movement, full_movement, self);
 
         if let Some((cost, _path)) = reachable.get(&target_hex) {
             self.move_unit(active_unit, target_hex, *cost);
         }
     }
 
+    /// Like items, only the active hero's abilities can be picked, and only while that hero is
+    /// selected, since `try_use_ability` casts from the active hero.
     fn select_ability(&mut self, ability_idx: usize) {
-        self.selected_ability = Some(ability_idx);
-        let caster = self.selected_unit.unwrap();
-        let ability = &self.unit_abilities(caster).unwrap()[ability_idx];
+        let caster = self.active_unit();
+        if !matches!(caster, UnitRef::Hero(_)) || self.selected_unit != Some(caster) {
+            return;
+        }
+        let Some(ability) = self
+            .unit_abilities(caster)
+            .and_then(|abilities| abilities.get(ability_idx))
+        else {
+            return;
+        };
         let caster_hex = self.unit_hex(caster).unwrap();
+        let (range, blocked) = self.ability_area(caster, ability, caster_hex);
 
-        self.selected_ability_range =
-            caster_hex.range_to_area(ability.range, self.grid_width, self.grid_height);
+        self.selected_item = None;
+        self.selected_ability = Some(ability_idx);
+        self.selected_ability_range = range;
+        self.selected_ability_blocked = blocked;
         self.input_mode = InputMode::AbilityTarget(ability_idx);
     }
 
@@ -739,6 +1085,19 @@ impl BattleState {
             eprintln!("WARNING: ABILITY USED DURING ENEMY TURN");
             return;
         };
+        if self.selected_unit != Some(UnitRef::Hero(caster_ref)) {
+            return;
+        }
+
+        if !self.heroes[&caster_ref].action_available {
+            println!("Action already used this turn");
+            return;
+        }
+
+        if self.selected_ability_blocked.contains(&target) {
+            println!("NO LINE OF SIGHT");
+            return;
+        }
 
         if !self.selected_ability_range.contains(&target) {
             println!("TARGET TOO FAR");
@@ -753,25 +1112,7 @@ impl BattleState {
             }
 
             let ability_index = self.selected_ability.unwrap();
-            let damage_modifier;
-            let effect;
-
-            {
-                let caster = self.heroes.get(&caster_ref).unwrap();
-                let ability = &caster.abilities[ability_index];
-                damage_modifier = ability.damage_modifier;
-                effect = ability.effect.clone();
-            }
-
-            self.attack_unit(self.active_unit(), target_unit, damage_modifier);
-
-            if let Some(e) = effect {
-                if let Some(targetted_enemy_instance) = self.unit_mut(target_unit) {
-                    targetted_enemy_instance.effects_mut().push(e);
-                } else {
-                    println!("Target died, effect skipped");
-                }
-            }
+            self.use_ability(self.active_unit(), ability_index, target_unit);
 
             if let Some(caster_mut) = self.heroes.get_mut(&caster_ref) {
                 caster_mut.action_available = false;
@@ -783,9 +1124,85 @@ impl BattleState {
         }
     }
 
+    /// Only the active hero's items can be picked, and only while that hero is selected, since
+    /// `try_use_item` spends the item from the active hero.
+    fn select_item(&mut self, item_idx: usize) {
+        let active = self.active_unit();
+        let UnitRef::Hero(hero_id) = active else {
+            return;
+        };
+        if self.selected_unit != Some(active) {
+            return;
+        }
+        let Some(consumable) = self
+            .heroes
+            .get(&hero_id)
+            .and_then(|h| h.items.get(item_idx))
+            .and_then(|item| item.consumable.clone())
+        else {
+            return;
+        };
+
+        self.selected_ability = None;
+        self.selected_item = Some(item_idx);
+        let hero_hex = self.heroes[&hero_id].hex;
+        self.selected_ability_range =
+            hero_hex.range_to_area(consumable.range(), self.grid_width, self.grid_height);
+        self.selected_ability_blocked.clear();
+        self.input_mode = InputMode::ItemTarget(item_idx);
+    }
+
+    fn try_use_item(&mut self, target: Hex) {
+        let UnitRef::Hero(caster_id) = self.active_unit() else {
+            eprintln!("WARNING: ITEM USED DURING ENEMY TURN");
+            return;
+        };
+        let Some(item_idx) = self.selected_item else {
+            return;
+        };
+        if self.selected_unit != Some(UnitRef::Hero(caster_id)) {
+            return;
+        }
+
+        if !self.heroes[&caster_id].action_available {
+            println!("Action already used this turn");
+            return;
+        }
+
+        if !self.selected_ability_range.contains(&target) {
+            println!("TARGET TOO FAR");
+            return;
+        }
+
+        let Some(target_unit) = self.hex_map[&target].occupying_unit else {
+            return;
+        };
+        let Some(item) = self.heroes[&caster_id].items.get(item_idx).cloned() else {
+            return;
+        };
+        let Some(consumable) = item.consumable else {
+            return;
+        };
+
+        if consumable.targets_allies() != matches!(target_unit, UnitRef::Hero(_)) {
+            println!("Cannot use {} on that unit", item.name);
+            return;
+        }
+
+        let caster = self.heroes.get_mut(&caster_id).unwrap();
+        caster.items.remove(item_idx);
+        caster.action_available = false;
+        self.consumed_items.push(item.id);
+
+        self.use_consumable(&consumable, target_unit);
+        self.clear_ability_selection();
+    }
+
     fn clear_ability_selection(&mut self) {
         self.selected_ability = None;
+        self.selected_item = None;
         self.selected_ability_range.clear();
+        self.selected_ability_blocked.clear();
         self.input_mode = InputMode::Movement;
     }
 
@@ -797,11 +1214,16 @@ impl BattleState {
 }
 
 pub trait Unit {
+    #[allow(dead_code)]
     fn hex(&self) -> Hex;
     fn stats(&self) -> &Stats;
     fn stats_mut(&mut self) -> &mut Stats;
+    #[allow(dead_code)]
     fn effects(&self) -> &Vec<Effect>;
     fn effects_mut(&mut self) -> &mut Vec<Effect>;
+    fn on_hit_effects(&self) -> &[Effect] {
+        &[]
+    }
 }
 
 impl Unit for HeroInstance {
@@ -820,6 +1242,9 @@ impl Unit for HeroInstance {
     fn effects_mut(&mut self) -> &mut Vec<Effect> {
         &mut self.effects
     }
+    fn on_hit_effects(&self) -> &[Effect] {
+        &self.on_hit_effects
+    }
 }
 
 impl Unit for EnemyInstance {
@@ -839,3 +1264,469 @@ impl Unit for EnemyInstance {
         &mut self.effects
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex;
+    use crate::item::{Consumable, ItemSlot, Stat};
+    use crate::loot::{LootDrop, LootEntry};
+    use crate::testing::*;
+
+    #[test]
+    fn hero_defeats_goblin() {
+        let heroes = [hero(0, stats(200, (20, 25), 12, 2))];
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 15, 3))];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+
+        let result = run_battle(&mut battle, 500, charge_closest_enemy);
+
+        assert!(matches!(result, Some(BattleResult::Victory)));
+        assert!(battle.enemies.is_empty());
+        assert_eq!(battle.turn_order, vec![UnitRef::Hero(0)]);
+        assert_eq!(battle.xp_earned, 10);
+    }
+
+    #[test]
+    fn passive_hero_is_defeated() {
+        let heroes = [hero(0, stats(10, (1, 2), 5, 2))];
+        let enemies = [
+            enemy(0, "Goblin", stats(30, (10, 12), 15, 3)),
+            enemy(1, "Orc", stats(60, (15, 20), 8, 2)),
+        ];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+
+        let result = run_battle(&mut battle, 500, pass_turn);
+
+        assert!(matches!(result, Some(BattleResult::Defeat)));
+        assert!(battle.heroes.is_empty());
+    }
+
+    #[test]
+    fn poison_kills_at_turn_start() {
+        let heroes = [hero(0, stats(1000, (1, 1), 5, 2))];
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 1), 15, 3))];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+        battle.enemies.get_mut(&0).unwrap().effects = vec![Effect::Poison(20, 3)];
+
+        battle.tick(); // goblin start: 30 -> 10
+        assert_eq!(battle.unit_current_health(UnitRef::Enemy(0)), 10);
+        assert_eq!(battle.enemies[&0].effects[0].remaining_turns(), 2);
+
+        let result = run_battle(&mut battle, 50, pass_turn);
+
+        assert!(matches!(result, Some(BattleResult::Victory)));
+        assert_eq!(battle.turn_order, vec![UnitRef::Hero(0)]);
+    }
+
+    #[test]
+    fn reduced_movement_wears_off() {
+        let heroes = [hero(0, stats(1000, (1, 1), 15, 2))];
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 1), 5, 3))];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+        battle.heroes.get_mut(&0).unwrap().effects = vec![Effect::ReduceMovement(1, 2)];
+
+        let mut movement_per_turn = Vec::new();
+        run_battle(&mut battle, 30, |battle| {
+            movement_per_turn.push(battle.heroes[&0].current_movement);
+            pass_turn(battle);
+        });
+
+        assert_eq!(movement_per_turn[..4], [1, 1, 2, 2]);
+        assert!(battle.heroes[&0].effects.is_empty());
+    }
+
+    fn play_seeded(seed: u64) -> (Option<BattleResult>, Vec<(UnitRef, i32)>) {
+        let heroes = [hero(0, stats(120, (8, 16), 12, 2))];
+        let enemies = [
+            enemy(0, "Goblin", stats(30, (5, 10), 12, 3)),
+            enemy(1, "Orc", stats(60, (6, 12), 12, 2)),
+        ];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), seed).unwrap();
+        let mut health = Vec::new();
+
+        let result = run_battle(&mut battle, 1000, |battle| {
+            health.extend(
+                battle
+                    .turn_order
+                    .iter()
+                    .map(|&u| (u, battle.unit_current_health(u))),
+            );
+            charge_closest_enemy(battle);
+        });
+
+        (result, health)
+    }
+
+    #[test]
+    fn same_seed_replays_the_same_battle() {
+        let (result, health) = play_seeded(1234);
+        let (replayed_result, replayed_health) = play_seeded(1234);
+
+        assert!(result.is_some());
+        assert_eq!(format!("{:?}", result), format!("{:?}", replayed_result));
+        assert_eq!(health, replayed_health);
+    }
+
+    #[test]
+    fn seed_is_stored_with_the_battle() {
+        let battle =
+            BattleState::new(&[hero(0, stats(10, (1, 1), 1, 1))], &[], &test_map(), 99).unwrap();
+        assert_eq!(battle.seed, 99);
+    }
+
+    #[test]
+    fn units_spawn_on_map_spawn_zones() {
+        let map = test_map();
+        let heroes = [hero(0, stats(10, (1, 1), 1, 1))];
+        let enemies = [enemy(0, "Goblin", stats(10, (1, 1), 1, 1))];
+        let battle = BattleState::new(&heroes, &enemies, &map, 1).unwrap();
+
+        assert_eq!(battle.heroes[&0].hex, map.hero_spawns[0]);
+        assert_eq!(battle.enemies[&0].hex, map.enemy_spawns[0]);
+        assert_eq!(battle.grid_width, map.width);
+    }
+
+    #[test]
+    fn too_few_spawns_is_an_error() {
+        let mut map = test_map();
+        map.enemy_spawns.truncate(1);
+        let enemies = [
+            enemy(0, "Goblin", stats(10, (1, 1), 1, 1)),
+            enemy(1, "Orc", stats(10, (1, 1), 1, 1)),
+        ];
+
+        assert!(BattleState::new(&[], &enemies, &map, 1).is_err());
+    }
+
+    #[test]
+    fn units_sharing_an_id_are_an_error() {
+        let enemies = [
+            enemy(4, "Goblin", stats(10, (1, 1), 1, 1)),
+            enemy(4, "Orc", stats(10, (1, 1), 1, 1)),
+        ];
+
+        assert!(BattleState::new(&[], &enemies, &test_map(), 1).is_err());
+        let battle = BattleState::new(&[], &enemies[..1], &test_map(), 1).unwrap();
+        assert_eq!(battle.enemies[&4].name, "Goblin");
+    }
+
+    #[test]
+    fn turn_passes_on_when_first_unit_dies() {
+        let heroes = [hero(0, stats(1000, (1, 1), 10, 2))];
+        let enemies = [
+            enemy(0, "Goblin", stats(30, (1, 1), 15, 3)),
+            enemy(1, "Orc", stats(60, (1, 1), 5, 2)),
+        ];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+        battle.enemies.get_mut(&0).unwrap().effects = vec![Effect::Poison(100, 1)];
+        assert_eq!(battle.active_unit(), UnitRef::Enemy(0));
+
+        battle.tick(); // goblin dies at turn start
+        battle.tick(); // end phase
+
+        assert_eq!(battle.active_unit(), UnitRef::Hero(0));
+        assert_eq!(battle.turn_order, vec![UnitRef::Hero(0), UnitRef::Enemy(1)]);
+    }
+
+    #[test]
+    fn equipped_weapon_poisons_on_hit() {
+        let mut fighter = hero(0, stats(100, (8, 16), 12, 2));
+        let mut dagger = item(1, ItemSlot::MainHand, vec![(Stat::Attack, 5)]);
+        dagger.effects.push(Effect::Poison(3, 2));
+        fighter.inventory.equip(ItemSlot::MainHand, dagger).unwrap();
+        let enemies = [enemy(0, "Goblin", stats(100, (1, 2), 15, 3))];
+        let mut battle = BattleState::new(&[fighter], &enemies, &test_map(), 7).unwrap();
+
+        assert_eq!(battle.heroes[&0].stats.attack, 15);
+        battle.use_ability(UnitRef::Hero(0), 0, UnitRef::Enemy(0));
+
+        assert!(matches!(
+            battle.enemies[&0].effects[..],
+            [Effect::Poison(3, 2)]
+        ));
+    }
+
+    #[test]
+    fn saved_battle_resumes_with_the_same_rolls() {
+        let heroes = [hero(0, stats(120, (8, 16), 12, 2))];
+        let enemies = [
+            enemy(0, "Goblin", stats(30, (5, 10), 15, 3)),
+            enemy(1, "Orc", stats(60, (6, 12), 5, 2)),
+        ];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 99).unwrap();
+        for _ in 0..3 {
+            battle.tick();
+            if battle.awaiting_input() {
+                charge_closest_enemy(&mut battle);
+            }
+        }
+        let json = serde_json::to_string(&battle).unwrap();
+        let mut resumed: BattleState = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(resumed.turn_order, battle.turn_order);
+        assert_eq!(resumed.active_unit_idx, battle.active_unit_idx);
+        assert_eq!(resumed.phase, battle.phase);
+
+        run_battle(&mut battle, 1000, charge_closest_enemy);
+        run_battle(&mut resumed, 1000, charge_closest_enemy);
+        assert_eq!(resumed.log.entries, battle.log.entries);
+        assert_eq!(
+            format!("{:?}", resumed.result),
+            format!("{:?}", battle.result)
+        );
+        for unit in &battle.turn_order {
+            assert_eq!(
+                resumed.unit_current_health(*unit),
+                battle.unit_current_health(*unit)
+            );
+        }
+    }
+
+    fn until_hero_turn(battle: &mut BattleState) {
+        while !battle.awaiting_input() {
+            battle.tick();
+        }
+    }
+
+    #[test]
+    fn using_an_item_costs_the_action_and_uses_it_up() {
+        let mut fighter = hero(0, stats(100, (8, 16), 20, 2));
+        fighter.stats.hp = 40;
+        fighter
+            .inventory
+            .add_to_backpack(consumable(7, "Potion", Consumable::Heal(30)));
+        fighter
+            .inventory
+            .add_to_backpack(consumable(8, "Potion", Consumable::Heal(30)));
+        fighter
+            .inventory
+            .add_to_backpack(item(9, ItemSlot::Head, vec![]));
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 5, 3))];
+        let mut battle = BattleState::new(&[fighter], &enemies, &test_map(), 7).unwrap();
+        until_hero_turn(&mut battle);
+        let hero_hex = battle.heroes[&0].hex;
+
+        assert_eq!(battle.heroes[&0].items.len(), 2);
+        battle.handle_command(BattleCommand::SelectItem(0));
+        battle.handle_command(BattleCommand::UseItem(hero_hex));
+        battle.handle_command(BattleCommand::SelectItem(0));
+        battle.handle_command(BattleCommand::UseItem(hero_hex));
+
+        assert_eq!(battle.heroes[&0].stats.hp, 70);
+        assert!(!battle.heroes[&0].action_available);
+        assert_eq!(battle.heroes[&0].items.len(), 1);
+        assert_eq!(battle.consumed_items, vec![7]);
+    }
+
+    #[test]
+    fn throwables_hit_enemies_and_antidotes_cure_poison() {
+        let fighter = hero(0, stats(100, (8, 16), 20, 2));
+        let flask = Consumable::Throwable {
+            range: 3,
+            damage: 5,
+            effect: Some(Effect::Poison(5, 3)),
+        };
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 5, 3))];
+        let mut battle = BattleState::new(&[fighter], &enemies, &test_map(), 7).unwrap();
+
+        battle.use_consumable(&flask, UnitRef::Enemy(0));
+        battle
+            .heroes
+            .get_mut(&0)
+            .unwrap()
+            .effects
+            .push(Effect::Poison(5, 3));
+        battle.use_consumable(&Consumable::Antidote, UnitRef::Hero(0));
+
+        assert_eq!(battle.enemies[&0].stats.hp, 25);
+        assert_eq!(battle.enemies[&0].effects.len(), 1);
+        assert!(battle.heroes[&0].effects.is_empty());
+    }
+
+    #[test]
+    fn defeated_enemies_drop_loot() {
+        let heroes = [hero(0, stats(100, (8, 16), 12, 2))];
+        let mut goblin = enemy(0, "Goblin", stats(10, (1, 2), 5, 3));
+        goblin.loot = vec![
+            LootEntry {
+                chance: 1.0,
+                drop: LootDrop::Item("healing_potion".to_string()),
+            },
+            LootEntry {
+                chance: 0.0,
+                drop: LootDrop::Item("rusty_sword".to_string()),
+            },
+            LootEntry {
+                chance: 1.0,
+                drop: LootDrop::Material {
+                    id: "iron_ore".to_string(),
+                    min: 2,
+                    max: 2,
+                },
+            },
+        ];
+        let mut battle = BattleState::new(&heroes, &[goblin], &test_map(), 7).unwrap();
+        let bomb = Consumable::Throwable {
+            range: 5,
+            damage: 50,
+            effect: None,
+        };
+
+        assert!(battle.loot.items.is_empty() && battle.loot.materials.is_empty());
+        battle.use_consumable(&bomb, UnitRef::Enemy(0));
+
+        assert_eq!(battle.loot.items, ["healing_potion"]);
+        assert_eq!(battle.loot.materials["iron_ore"], 2);
+    }
+
+    #[test]
+    fn abilities_needing_a_clear_line_cannot_target_past_mountains() {
+        let mut fighter = hero(0, stats(100, (8, 16), 20, 2));
+        fighter.abilities = vec![Ability {
+            range: 3,
+            line_of_sight: LineOfSight::Terrain,
+            ..slash()
+        }];
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 5, 3))];
+        let mut battle = BattleState::new(&[fighter], &enemies, &test_map(), 7).unwrap();
+        until_hero_turn(&mut battle);
+        // Mountains on (3,4) and (4,4) stand in between.
+        battle.heroes.get_mut(&0).unwrap().hex = hex!(2, 4);
+        battle.enemies.get_mut(&0).unwrap().hex = hex!(5, 4);
+        battle.update_occupied_hexes();
+
+        battle.handle_command(BattleCommand::SelectAbility(0));
+        assert!(battle.selected_ability_blocked.contains(&hex!(5, 4)));
+        assert!(!battle.selected_ability_range.contains(&hex!(5, 4)));
+        assert!(battle.selected_ability_range.contains(&hex!(2, 6)));
+        battle.handle_command(BattleCommand::UseAbility(hex!(5, 4)));
+
+        assert_eq!(battle.enemies[&0].stats.hp, 30);
+        assert!(battle.heroes[&0].action_available);
+    }
+
+    #[test]
+    fn abilities_are_only_picked_by_the_active_hero() {
+        let mut first = hero(0, stats(100, (8, 16), 20, 2));
+        first.abilities = vec![Ability {
+            range: 3,
+            line_of_sight: LineOfSight::Terrain,
+            ..slash()
+        }];
+        let mut second = hero(1, stats(100, (8, 16), 10, 2));
+        second.abilities = vec![
+            slash(),
+            Ability {
+                range: 3,
+                ..slash()
+            },
+        ];
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 5, 3))];
+        let mut battle = BattleState::new(&[first, second], &enemies, &test_map(), 7).unwrap();
+        until_hero_turn(&mut battle);
+        assert_eq!(battle.active_unit(), UnitRef::Hero(0));
+        // Mountains on (3,4) and (4,4) stand between the active hero and the goblin.
+        battle.heroes.get_mut(&0).unwrap().hex = hex!(2, 4);
+        battle.heroes.get_mut(&1).unwrap().hex = hex!(5, 6);
+        battle.enemies.get_mut(&0).unwrap().hex = hex!(5, 4);
+        battle.update_occupied_hexes();
+
+        // The other hero's line and ability list don't carry over to the active one.
+        battle.handle_command(BattleCommand::SelectUnitAtHex(hex!(5, 6)));
+        battle.handle_command(BattleCommand::SelectAbility(1));
+        assert_eq!(battle.selected_ability, None);
+        battle.handle_command(BattleCommand::SelectAbility(0));
+        assert_eq!(battle.selected_ability, None);
+        battle.handle_command(BattleCommand::UseAbility(hex!(5, 4)));
+        assert_eq!(battle.enemies[&0].stats.hp, 30);
+
+        // Picking the ability and then looking at the other hero doesn't let it fire either.
+        battle.enemies.get_mut(&0).unwrap().hex = hex!(2, 6);
+        battle.update_occupied_hexes();
+        battle.handle_command(BattleCommand::SelectUnitAtHex(hex!(2, 4)));
+        battle.handle_command(BattleCommand::SelectAbility(1));
+        assert_eq!(battle.selected_ability, None);
+        battle.handle_command(BattleCommand::SelectAbility(0));
+        assert_eq!(battle.selected_ability, Some(0));
+        battle.handle_command(BattleCommand::SelectUnitAtHex(hex!(5, 6)));
+        battle.handle_command(BattleCommand::UseAbility(hex!(2, 6)));
+        assert_eq!(battle.enemies[&0].stats.hp, 30);
+
+        battle.handle_command(BattleCommand::SelectUnitAtHex(hex!(2, 4)));
+        battle.handle_command(BattleCommand::UseAbility(hex!(2, 6)));
+        assert!(battle.enemies[&0].stats.hp < 30);
+        assert!(!battle.heroes[&0].action_available);
+    }
+
+    #[test]
+    fn units_only_block_abilities_that_say_so() {
+        let heroes = [hero(0, stats(100, (8, 16), 20, 2))];
+        let enemies = [
+            enemy(0, "Goblin", stats(30, (1, 2), 5, 3)),
+            enemy(1, "Goblin", stats(30, (1, 2), 5, 3)),
+        ];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+        battle.heroes.get_mut(&0).unwrap().hex = hex!(5, 6);
+        battle.enemies.get_mut(&0).unwrap().hex = hex!(5, 7);
+        battle.enemies.get_mut(&1).unwrap().hex = hex!(5, 9);
+        battle.update_occupied_hexes();
+        let caster = UnitRef::Hero(0);
+        let (from, to) = (hex!(5, 6), hex!(5, 9));
+
+        assert!(battle.has_line_of_sight(caster, from, to, LineOfSight::Ignore));
+        assert!(battle.has_line_of_sight(caster, from, to, LineOfSight::Terrain));
+        assert!(!battle.has_line_of_sight(caster, from, to, LineOfSight::TerrainAndUnits));
+        // The caster doesn't block its own line when planning from another hex.
+        assert!(battle.has_line_of_sight(
+            caster,
+            hex!(5, 5),
+            hex!(5, 7),
+            LineOfSight::TerrainAndUnits
+        ));
+    }
+
+    #[test]
+    fn items_are_only_picked_and_used_by_the_active_hero() {
+        let mut first = hero(0, stats(100, (8, 16), 20, 2));
+        first.stats.hp = 40;
+        first
+            .inventory
+            .add_to_backpack(consumable(7, "Potion", Consumable::Heal(30)));
+        let mut second = hero(1, stats(100, (8, 16), 10, 2));
+        second.stats.hp = 40;
+        second.inventory.add_to_backpack(consumable(
+            8,
+            "Bomb",
+            Consumable::Throwable {
+                range: 9,
+                damage: 50,
+                effect: None,
+            },
+        ));
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 5, 3))];
+        let mut battle = BattleState::new(&[first, second], &enemies, &test_map(), 7).unwrap();
+        until_hero_turn(&mut battle);
+        assert_eq!(battle.active_unit(), UnitRef::Hero(0));
+        let (first_hex, second_hex) = (battle.heroes[&0].hex, battle.heroes[&1].hex);
+
+        // Picking an item while looking at another hero does nothing.
+        battle.handle_command(BattleCommand::SelectUnitAtHex(second_hex));
+        battle.handle_command(BattleCommand::SelectItem(0));
+        assert_eq!(battle.selected_item, None);
+
+        // Selecting another hero after picking the item doesn't let it be used either.
+        battle.handle_command(BattleCommand::SelectUnitAtHex(first_hex));
+        battle.handle_command(BattleCommand::SelectItem(0));
+        battle.handle_command(BattleCommand::SelectUnitAtHex(second_hex));
+        battle.handle_command(BattleCommand::UseItem(first_hex));
+        assert_eq!(battle.heroes[&0].stats.hp, 40);
+        assert_eq!(battle.heroes[&0].items.len(), 1);
+
+        battle.handle_command(BattleCommand::SelectUnitAtHex(first_hex));
+        battle.handle_command(BattleCommand::UseItem(first_hex));
+        assert_eq!(battle.heroes[&0].stats.hp, 70);
+        assert!(battle.heroes[&0].items.is_empty());
+        assert_eq!(battle.heroes[&1].items.len(), 1);
+    }
+}
@@ -1,7 +1,9 @@
+use crate::ai::{AiBehavior, AiWeights};
 use crate::effect::Effect;
 use crate::hexgrid::Hex;
 use crate::inventory::Inventory;
-use macroquad::prelude::*;
+use crate::item::{ItemSlot, Stat};
+use crate::loot::LootEntry;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -15,19 +17,116 @@ pub struct Stats {
     pub movement: i32,
 }
 
+impl Stats {
+    /// Shifts a stat by `amount`. Current HP is left alone, bounding it is up to the caller so
+    /// it isn't cut down by a lower max HP halfway through applying several changes.
+    pub fn modify(&mut self, stat: Stat, amount: i32) {
+        match stat {
+            Stat::MaxHp => self.max_hp = (self.max_hp + amount).max(1),
+            Stat::Damage => {
+                self.damage.0 = (self.damage.0 + amount).max(0);
+                self.damage.1 = (self.damage.1 + amount).max(self.damage.0);
+            }
+            Stat::Attack => self.attack += amount,
+            Stat::Defense => self.defense += amount,
+            Stat::Initiative => self.initiative += amount,
+            Stat::Movement => self.movement = (self.movement + amount).max(0),
+        }
+    }
+}
+
+/// Total XP needed to reach level 2, 3, and so on. Heroes start at level 1.
+pub const LEVEL_THRESHOLDS: [u64; 9] = [100, 250, 450, 700, 1000, 1400, 1900, 2500, 3200];
+pub const SKILL_POINTS_PER_LEVEL: u32 = 1;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Hero {
     pub id: u32,
     pub name: String,
+    /// Picks the skill tree in `data/content/skill_trees.json`.
+    #[serde(default)]
+    pub class: String,
 
     pub hex: Hex,
     pub stats: Stats,
     pub xp: u64,
+    /// Stat changes applied on every level up.
+    #[serde(default)]
+    pub growth: Vec<(Stat, i32)>,
+    #[serde(default)]
+    pub skill_points: u32,
+    /// Ids of skill tree nodes the hero has unlocked.
+    #[serde(default)]
+    pub unlocked_skills: Vec<String>,
     pub abilities: Vec<Ability>,
     pub inventory: Inventory,
     pub effects: Vec<Effect>,
 }
 
+impl Hero {
+    pub fn level(&self) -> u32 {
+        1 + LEVEL_THRESHOLDS.iter().filter(|&&t| self.xp >= t).count() as u32
+    }
+
+    /// Adds XP, applies stat growth and hands out skill points for every level gained. Returns
+    /// the number of levels gained.
+    pub fn gain_xp(&mut self, amount: u64) -> u32 {
+        let before = self.level();
+        self.xp += amount;
+        let gained = self.level() - before;
+
+        self.skill_points += gained * SKILL_POINTS_PER_LEVEL;
+        let growth = self.growth.clone();
+        for _ in 0..gained {
+            for &(stat, amount) in &growth {
+                self.raise_stat(stat, amount);
+            }
+        }
+        gained
+    }
+
+    /// Permanently changes a base stat. Extra max HP is granted as current HP too. Current HP
+    /// is bounded by the max HP with equipment, not the base one.
+    pub fn raise_stat(&mut self, stat: Stat, amount: i32) {
+        let hp = self.stats.hp;
+        self.stats.modify(stat, amount);
+        let gained = if stat == Stat::MaxHp { amount } else { 0 };
+        self.stats.hp = (hp + gained).clamp(1, self.effective_stats().max_hp);
+    }
+
+    /// Base stats with every equipped item's modifiers applied. The modifiers are added up per
+    /// stat before any limits apply, so the result doesn't depend on the order of the items.
+    pub fn effective_stats(&self) -> Stats {
+        let mut totals: Vec<(Stat, i32)> = Vec::new();
+        let items = ItemSlot::ALL
+            .iter()
+            .filter_map(|slot| self.inventory.equipped.get(slot));
+        for &(stat, amount) in items.flat_map(|item| &item.stat_changes) {
+            match totals.iter_mut().find(|(s, _)| *s == stat) {
+                Some((_, total)) => *total += amount,
+                None => totals.push((stat, amount)),
+            }
+        }
+
+        let mut stats = self.stats.clone();
+        for (stat, amount) in totals {
+            stats.modify(stat, amount);
+        }
+        stats.hp = stats.hp.min(stats.max_hp);
+        stats
+    }
+
+    /// Effects of equipped items, applied to whatever the hero hits.
+    pub fn on_hit_effects(&self) -> Vec<Effect> {
+        let mut items: Vec<_> = self.inventory.equipped.values().collect();
+        items.sort_by_key(|item| item.id);
+        items
+            .into_iter()
+            .flat_map(|item| item.effects.iter().cloned())
+            .collect()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Enemy {
     pub id: u32,
@@ -35,7 +134,17 @@ pub struct Enemy {
 
     pub hex: Hex,
     pub stats: Stats,
+    #[serde(default)]
+    pub abilities: Vec<Ability>,
     pub effects: Vec<Effect>,
+    #[serde(default)]
+    pub xp_reward: u64,
+    #[serde(default)]
+    pub loot: Vec<LootEntry>,
+    #[serde(default)]
+    pub ai: AiWeights,
+    #[serde(default)]
+    pub behavior: AiBehavior,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -46,4 +155,123 @@ pub struct Ability {
     pub damage_modifier: f32,
     pub effect: Option<Effect>,
     pub range: i32,
+    #[serde(default)]
+    pub line_of_sight: LineOfSight,
+}
+
+/// What has to be out of the way between the caster and the target of an ability.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum LineOfSight {
+    /// Reaches anything in range, like a lobbed potion or a spell.
+    #[default]
+    Ignore,
+    /// Blocked by terrain such as mountains.
+    Terrain,
+    /// Blocked by terrain and by any unit standing in the way.
+    TerrainAndUnits,
+}
+
+impl Ability {
+    /// Plain melee hit for units that don't have any abilities of their own.
+    pub fn basic_attack() -> Self {
+        Self {
+            id: u32::MAX,
+            name: "Attack".to_string(),
+            description: "A basic melee attack.".to_string(),
+            damage_modifier: 1.0,
+            effect: None,
+            range: 1,
+            line_of_sight: LineOfSight::Ignore,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn equipped_items_change_effective_stats() {
+        let mut fighter = hero(0, stats(50, (8, 12), 10, 2));
+        let sword = item(
+            1,
+            ItemSlot::MainHand,
+            vec![(Stat::Damage, 3), (Stat::Attack, 2)],
+        );
+        let boots = item(
+            2,
+            ItemSlot::Boots,
+            vec![(Stat::Movement, 1), (Stat::MaxHp, -60)],
+        );
+        fighter.inventory.equip(ItemSlot::MainHand, sword).unwrap();
+        fighter.inventory.equip(ItemSlot::Boots, boots).unwrap();
+
+        let effective = fighter.effective_stats();
+
+        assert_eq!(effective.damage, (11, 15));
+        assert_eq!(effective.attack, 12);
+        assert_eq!(effective.movement, 3);
+        assert_eq!((effective.max_hp, effective.hp), (1, 1));
+        assert_eq!(fighter.stats.damage, (8, 12));
+    }
+
+    #[test]
+    fn effective_stats_dont_depend_on_item_order() {
+        // Every new HashMap iterates in a different order, so equip the items afresh each time.
+        for _ in 0..20 {
+            let mut fighter = hero(0, stats(50, (8, 12), 10, 2));
+            fighter.stats.hp = 70;
+            let items = [
+                (ItemSlot::Accessory, vec![(Stat::MaxHp, 20)]),
+                (
+                    ItemSlot::Head,
+                    vec![(Stat::Defense, 2), (Stat::Damage, -10)],
+                ),
+                (ItemSlot::Boots, vec![(Stat::Movement, 1)]),
+                (ItemSlot::MainHand, vec![(Stat::Damage, 10)]),
+            ];
+            for (id, (slot, changes)) in items.into_iter().enumerate() {
+                fighter
+                    .inventory
+                    .equip(slot, item(id as u64, slot, changes))
+                    .unwrap();
+            }
+
+            let effective = fighter.effective_stats();
+            assert_eq!((effective.max_hp, effective.hp), (70, 70));
+            assert_eq!(effective.damage, (8, 12));
+            assert_eq!(effective.movement, 3);
+        }
+    }
+
+    #[test]
+    fn equip_rejects_item_for_another_slot() {
+        let mut fighter = hero(0, stats(50, (8, 12), 10, 2));
+        let helmet = item(1, ItemSlot::Head, vec![(Stat::Defense, 2)]);
+
+        let rejected = fighter
+            .inventory
+            .equip(ItemSlot::Boots, helmet)
+            .unwrap_err();
+
+        assert_eq!(rejected.id, 1);
+        assert!(fighter.inventory.equipped.is_empty());
+    }
+
+    #[test]
+    fn levelling_up_applies_growth_per_level() {
+        let mut fighter = hero(0, stats(50, (8, 12), 10, 2));
+        fighter.stats.hp = 20;
+        fighter.growth = vec![(Stat::MaxHp, 10), (Stat::Attack, 1)];
+
+        assert_eq!(fighter.gain_xp(99), 0);
+        assert_eq!(fighter.level(), 1);
+        assert_eq!(fighter.gain_xp(200), 2);
+
+        assert_eq!(fighter.level(), 3);
+        assert_eq!((fighter.stats.hp, fighter.stats.max_hp), (40, 70));
+        assert_eq!(fighter.stats.attack, 12);
+        assert_eq!(fighter.skill_points, 2);
+    }
 }
@@ -0,0 +1,535 @@
+use crate::adventure::AdventureConfig;
+use crate::ai::{AiBehavior, AiWeights};
+use crate::battlemap::BattleMap;
+use crate::character::{Ability, Enemy, Hero, Stats};
+use crate::crafting::Blueprint;
+use crate::hexgrid::Hex;
+use crate::inventory::Inventory;
+use crate::item::{Item, Stat};
+use crate::loot::{LootDrop, LootEntry};
+use crate::skilltree::SkillTree;
+use anyhow::{anyhow, bail, Context};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const CONTENT_DIR: &str = "data/content";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnemyArchetype {
+    pub name: String,
+    pub stats: Stats,
+    pub abilities: Vec<String>,
+    pub xp_reward: u64,
+    #[serde(default)]
+    pub loot: Vec<LootEntry>,
+    /// How the enemy weighs its options, see `AiWeights`.
+    #[serde(default)]
+    pub ai: AiWeights,
+    /// Behaviour unless the encounter picks another one.
+    #[serde(default)]
+    pub behavior: AiBehavior,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeroTemplate {
+    pub name: String,
+    pub class: String,
+    pub stats: Stats,
+    pub growth: Vec<(Stat, i32)>,
+    pub abilities: Vec<String>,
+    /// Item template ids the hero starts with in the backpack.
+    #[serde(default)]
+    pub starting_items: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Encounter {
+    pub map: String,
+    pub enemies: Vec<EncounterEnemy>,
+}
+
+/// Enemy archetype id, optionally with the behaviour it uses in this encounter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum EncounterEnemy {
+    Archetype(String),
+    WithBehavior { enemy: String, behavior: AiBehavior },
+}
+
+impl EncounterEnemy {
+    pub fn archetype(&self) -> &str {
+        match self {
+            EncounterEnemy::Archetype(id) | EncounterEnemy::WithBehavior { enemy: id, .. } => id,
+        }
+    }
+}
+
+/// Game content loaded from `data/content`. Everything is keyed by string ids, which files use
+/// to refer to each other.
+#[derive(Debug, Clone, Default)]
+pub struct ContentRegistry {
+    pub abilities: HashMap<String, Ability>,
+    pub items: HashMap<String, Item>,
+    pub blueprints: HashMap<String, Blueprint>,
+    pub enemies: HashMap<String, EnemyArchetype>,
+    pub heroes: Vec<HeroTemplate>,
+    pub encounters: HashMap<String, Encounter>,
+    /// Skill tree per hero class.
+    pub skill_trees: HashMap<String, SkillTree>,
+    /// How adventure maps are generated.
+    pub campaign: AdventureConfig,
+    /// Battle maps encounters are checked against, `maps` next to the content directory.
+    pub maps_dir: PathBuf,
+}
+
+impl ContentRegistry {
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(CONTENT_DIR)
+    }
+
+    pub fn load_from(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut content = Self {
+            abilities: read_json(&dir.join("abilities.json"))?,
+            items: read_json(&dir.join("items.json"))?,
+            blueprints: read_json(&dir.join("blueprints.json"))?,
+            enemies: read_json(&dir.join("enemies.json"))?,
+            heroes: read_json(&dir.join("heroes.json"))?,
+            encounters: read_json(&dir.join("encounters.json"))?,
+            skill_trees: read_json(&dir.join("skill_trees.json"))?,
+            campaign: read_json(&dir.join("campaign.json"))?,
+            maps_dir: dir.parent().unwrap_or(dir).join("maps"),
+        };
+        content.validate()?;
+        content.derive_salvage();
+        Ok(content)
+    }
+
+    /// Gives items without their own salvage yield part of their blueprint cost back.
+    fn derive_salvage(&mut self) {
+        let mut blueprints: Vec<_> = self.blueprints.iter().collect();
+        blueprints.sort_by(|a, b| a.0.cmp(b.0));
+        for (_, blueprint) in blueprints {
+            if let Some(item) = self.items.get_mut(&blueprint.result) {
+                if item.salvage.is_empty() {
+                    item.salvage = blueprint.salvage_yield();
+                }
+            }
+        }
+    }
+
+    /// Checks that every id referenced between files exists.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (id, enemy) in &self.enemies {
+            self.check_abilities(&enemy.abilities)
+                .and_then(|_| self.check_loot(&enemy.loot))
+                .with_context(|| format!("enemy {}", id))?;
+        }
+        for hero in &self.heroes {
+            self.check_abilities(&hero.abilities)
+                .with_context(|| format!("hero {}", hero.name))?;
+            if let Some(id) = hero
+                .starting_items
+                .iter()
+                .find(|id| !self.items.contains_key(*id))
+            {
+                bail!("hero {}: unknown item {}", hero.name, id);
+            }
+            if !self.skill_trees.contains_key(&hero.class) {
+                bail!("hero {}: no skill tree for class {}", hero.name, hero.class);
+            }
+        }
+        for (class, tree) in &self.skill_trees {
+            tree.validate()
+                .and_then(|_| {
+                    let granted: Vec<_> =
+                        tree.nodes.iter().flat_map(|n| n.ability.clone()).collect();
+                    self.check_abilities(&granted)
+                })
+                .with_context(|| format!("skill tree {}", class))?;
+        }
+        for (id, blueprint) in &self.blueprints {
+            if !self.items.contains_key(&blueprint.result) {
+                bail!("blueprint {}: unknown item {}", id, blueprint.result);
+            }
+        }
+        self.check_campaign().context("campaign")?;
+        for (id, encounter) in &self.encounters {
+            self.check_encounter(encounter)
+                .with_context(|| format!("encounter {}", id))?;
+        }
+        Ok(())
+    }
+
+    fn check_abilities(&self, ids: &[String]) -> anyhow::Result<()> {
+        match ids.iter().find(|id| !self.abilities.contains_key(*id)) {
+            Some(id) => bail!("unknown ability {}", id),
+            None => Ok(()),
+        }
+    }
+
+    fn check_loot(&self, loot: &[LootEntry]) -> anyhow::Result<()> {
+        for entry in loot {
+            if !(0.0..=1.0).contains(&entry.chance) {
+                bail!("loot chance {} is not between 0 and 1", entry.chance);
+            }
+            match &entry.drop {
+                LootDrop::Item(id) if !self.items.contains_key(id) => bail!("unknown item {}", id),
+                LootDrop::Material { id, min, max } if min > max => {
+                    bail!("material {} drops {} to {}", id, min, max)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_campaign(&self) -> anyhow::Result<()> {
+        let campaign = &self.campaign;
+        if campaign.layers < 2 || campaign.min_width == 0 || campaign.min_width > campaign.max_width
+        {
+            bail!("needs at least 2 layers and a width range of 1 or more");
+        }
+        for (kind, pool) in [
+            ("battles", &campaign.battles),
+            ("elite_battles", &campaign.elite_battles),
+            ("bosses", &campaign.bosses),
+        ] {
+            if pool.is_empty() {
+                bail!("no {}", kind);
+            }
+            if let Some(id) = pool.iter().find(|id| !self.encounters.contains_key(*id)) {
+                bail!("{}: unknown encounter {}", kind, id);
+            }
+        }
+        if let Some(id) = campaign
+            .merchant_stock
+            .iter()
+            .find(|id| !self.items.contains_key(*id))
+        {
+            bail!("merchant_stock: unknown item {}", id);
+        }
+        Ok(())
+    }
+
+    fn check_encounter(&self, encounter: &Encounter) -> anyhow::Result<()> {
+        if let Some(enemy) = encounter
+            .enemies
+            .iter()
+            .find(|e| !self.enemies.contains_key(e.archetype()))
+        {
+            bail!("unknown enemy {}", enemy.archetype());
+        }
+        let map = BattleMap::load_from(&self.maps_dir, &encounter.map)?;
+        if map.enemy_spawns.len() < encounter.enemies.len() {
+            bail!(
+                "map {} only has {} enemy spawns",
+                map.id,
+                map.enemy_spawns.len()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn ability(&self, id: &str) -> anyhow::Result<Ability> {
+        self.abilities
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown ability {}", id))
+    }
+
+    /// New item from a template, with the given instance id.
+    pub fn create_item(&self, template: &str, id: u64) -> anyhow::Result<Item> {
+        let mut item = self
+            .items
+            .get(template)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown item {}", template))?;
+        item.id = id;
+        Ok(item)
+    }
+
+    pub fn blueprint(&self, id: &str) -> anyhow::Result<Blueprint> {
+        self.blueprints
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown blueprint {}", id))
+    }
+
+    pub fn encounter(&self, id: &str) -> anyhow::Result<&Encounter> {
+        self.encounters
+            .get(id)
+            .ok_or_else(|| anyhow!("unknown encounter {}", id))
+    }
+
+    pub fn spawn_enemy(&self, archetype: &str, id: u32) -> anyhow::Result<Enemy> {
+        let archetype = self
+            .enemies
+            .get(archetype)
+            .ok_or_else(|| anyhow!("unknown enemy {}", archetype))?;
+        Ok(Enemy {
+            id,
+            name: archetype.name.clone(),
+            hex: Hex { q: 0, r: 0 },
+            stats: archetype.stats.clone(),
+            abilities: archetype
+                .abilities
+                .iter()
+                .map(|id| self.ability(id))
+                .collect::<anyhow::Result<_>>()?,
+            effects: Vec::new(),
+            xp_reward: archetype.xp_reward,
+            loot: archetype.loot.clone(),
+            ai: archetype.ai.clone(),
+            behavior: archetype.behavior,
+        })
+    }
+
+    pub fn encounter_enemies(&self, encounter: &Encounter) -> anyhow::Result<Vec<Enemy>> {
+        encounter
+            .enemies
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let mut enemy = self.spawn_enemy(entry.archetype(), i as u32)?;
+                if let EncounterEnemy::WithBehavior { behavior, .. } = entry {
+                    enemy.behavior = *behavior;
+                }
+                Ok(enemy)
+            })
+            .collect()
+    }
+
+    /// Spends the hero's skill points on a node of their class tree and grants what it gives.
+    pub fn unlock_skill(&self, hero: &mut Hero, node_id: &str) -> anyhow::Result<()> {
+        let tree = self
+            .skill_trees
+            .get(&hero.class)
+            .ok_or_else(|| anyhow!("unknown class {}", hero.class))?;
+        let node = tree.unlockable(hero, node_id)?;
+        let ability = node
+            .ability
+            .as_deref()
+            .map(|id| self.ability(id))
+            .transpose()?;
+
+        hero.skill_points -= node.cost;
+        hero.unlocked_skills.push(node.id.clone());
+        hero.abilities.extend(ability);
+        for &(stat, amount) in &node.stats {
+            hero.raise_stat(stat, amount);
+        }
+        Ok(())
+    }
+
+    pub fn starting_party(&self) -> anyhow::Result<Vec<Hero>> {
+        let mut next_item_id = 1;
+        self.heroes
+            .iter()
+            .enumerate()
+            .map(|(i, template)| {
+                let mut inventory = Inventory::new();
+                for id in &template.starting_items {
+                    inventory.add_to_backpack(self.create_item(id, next_item_id)?);
+                    next_item_id += 1;
+                }
+                Ok(Hero {
+                    id: i as u32,
+                    name: template.name.clone(),
+                    class: template.class.clone(),
+                    hex: Hex { q: 0, r: 0 },
+                    stats: template.stats.clone(),
+                    xp: 0,
+                    growth: template.growth.clone(),
+                    skill_points: 0,
+                    unlocked_skills: Vec::new(),
+                    abilities: template
+                        .abilities
+                        .iter()
+                        .map(|id| self.ability(id))
+                        .collect::<anyhow::Result<_>>()?,
+                    inventory,
+                    effects: Vec::new(),
+                })
+            })
+            .collect()
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let json =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("parsing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battlemap::MAPS_DIR;
+    use crate::battlestate::BattleState;
+    use crate::pathfinding::movement_range;
+    use std::collections::HashSet;
+
+    #[test]
+    fn encounter_maps_are_checked_next_to_the_content() {
+        let root = std::env::temp_dir().join(format!("bigtask-content-{}", std::process::id()));
+        for (from, to) in [(CONTENT_DIR, "content"), (MAPS_DIR, "maps")] {
+            std::fs::create_dir_all(root.join(to)).unwrap();
+            for entry in std::fs::read_dir(from).unwrap() {
+                let path = entry.unwrap().path();
+                std::fs::copy(&path, root.join(to).join(path.file_name().unwrap())).unwrap();
+            }
+        }
+
+        let loaded = ContentRegistry::load_from(root.join("content"));
+        std::fs::remove_file(root.join("maps").join("clearing.json")).unwrap();
+        let missing_map = ContentRegistry::load_from(root.join("content"));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(loaded.unwrap().maps_dir, root.join("maps"));
+        let err = format!("{:#}", missing_map.unwrap_err());
+        assert!(err.contains("clearing"), "{}", err);
+    }
+
+    #[test]
+    fn shipped_content_is_valid() {
+        let content = ContentRegistry::load().unwrap();
+
+        let party = content.starting_party().unwrap();
+        assert!(!party.is_empty());
+        assert!(party.iter().all(|h| !h.abilities.is_empty()));
+
+        for encounter in content.encounters.values() {
+            content.encounter_enemies(encounter).unwrap();
+        }
+    }
+
+    #[test]
+    fn enemy_spawns_are_reachable_at_the_shipped_movement() {
+        let content = ContentRegistry::load().unwrap();
+        let hero_movement = content.heroes.iter().map(|h| h.stats.movement);
+
+        for encounter in content.encounters.values() {
+            let map = BattleMap::load_from(&content.maps_dir, &encounter.map).unwrap();
+            let battle = BattleState::new(&[], &[], &map, 0).unwrap();
+            let enemies = content.encounter_enemies(encounter).unwrap();
+            let slowest = hero_movement
+                .clone()
+                .chain(enemies.iter().map(|e| e.stats.movement))
+                .min()
+                .unwrap();
+
+            // Walk turn by turn, each turn starting with full movement.
+            let mut reachable = HashSet::from([map.hero_spawns[0]]);
+            let mut frontier = vec![map.hero_spawns[0]];
+            while let Some(hex) = frontier.pop() {
+                for next in movement_range(hex, slowest, slowest, &battle).into_keys() {
+                    if reachable.insert(next) {
+                        frontier.push(next);
+                    }
+                }
+            }
+
+            for spawn in &map.enemy_spawns {
+                assert!(
+                    reachable.contains(spawn),
+                    "{}: {:?} can't be reached",
+                    encounter.map,
+                    spawn
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn encounter_with_unknown_enemy_is_rejected() {
+        let mut content = ContentRegistry::load().unwrap();
+        content.encounters.insert(
+            "broken".to_string(),
+            Encounter {
+                map: "clearing".to_string(),
+                enemies: vec![
+                    EncounterEnemy::Archetype("goblin".to_string()),
+                    EncounterEnemy::Archetype("dragon".to_string()),
+                ],
+            },
+        );
+
+        let err = content.validate().unwrap_err();
+        assert!(format!("{:#}", err).contains("dragon"));
+    }
+
+    #[test]
+    fn encounters_can_override_enemy_behaviour() {
+        let content = ContentRegistry::load().unwrap();
+        let encounter: Encounter = serde_json::from_str(
+            r#"{
+                "map": "clearing",
+                "enemies": ["orc", { "enemy": "goblin", "behavior": { "Guardian": { "radius": 2 } } }]
+            }"#,
+        )
+        .unwrap();
+
+        let enemies = content.encounter_enemies(&encounter).unwrap();
+
+        assert_eq!(enemies[0].behavior, content.enemies["orc"].behavior);
+        assert_eq!(enemies[1].behavior, AiBehavior::Guardian { radius: 2 });
+    }
+
+    #[test]
+    fn campaign_with_unknown_encounter_or_empty_pool_is_rejected() {
+        let mut content = ContentRegistry::load().unwrap();
+        content.campaign.bosses = vec!["dragon_lair".to_string()];
+        let err = content.validate().unwrap_err();
+        assert!(format!("{:#}", err).contains("dragon_lair"));
+
+        content.campaign.bosses.clear();
+        assert!(content.validate().is_err());
+    }
+
+    #[test]
+    fn unlocking_a_skill_spends_points_and_grants_it() {
+        let content = ContentRegistry::load().unwrap();
+        let mut fighter = content.starting_party().unwrap().remove(0);
+        let abilities = fighter.abilities.len();
+        fighter.skill_points = 2;
+
+        assert!(content
+            .unlock_skill(&mut fighter, "crippling_strike")
+            .is_err());
+        content
+            .unlock_skill(&mut fighter, "weapon_training")
+            .unwrap();
+        content
+            .unlock_skill(&mut fighter, "crippling_strike")
+            .unwrap();
+
+        assert_eq!(fighter.skill_points, 0);
+        assert_eq!(
+            fighter.unlocked_skills,
+            ["weapon_training", "crippling_strike"]
+        );
+        assert_eq!(fighter.abilities.len(), abilities + 1);
+        assert_eq!(fighter.abilities.last().unwrap().name, "Crippling Strike");
+        assert!(content.unlock_skill(&mut fighter, "toughness").is_err());
+    }
+
+    #[test]
+    fn unknown_ability_and_map_are_rejected() {
+        let mut content = ContentRegistry::load().unwrap();
+        content.heroes[0].abilities.push("fireball".to_string());
+        assert!(content.validate().is_err());
+
+        let mut content = ContentRegistry::load().unwrap();
+        content.encounters.insert(
+            "nowhere".to_string(),
+            Encounter {
+                map: "no_such_map".to_string(),
+                enemies: vec![],
+            },
+        );
+        assert!(content.validate().is_err());
+    }
+}
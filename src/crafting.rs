@@ -1,24 +1,112 @@
-use crate::item::Item;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone)]
+/// Recipe from `data/content/blueprints.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blueprint {
-    pub id: u32,
-    pub result: Item,
-    pub cost: HashMap<String, u32>,
+    /// Item template id of the crafted item.
+    pub result: String,
+    pub cost: BTreeMap<String, u32>,
 }
 
 impl Blueprint {
-    pub fn craft(&self, materials: &mut HashMap<String, u32>) -> Option<Item> {
-        // sprawdzamy materiały
-        for (k, v) in &self.cost {
-            if materials.get(k).unwrap_or(&0) < v {
-                return None;
-            }
+    /// What salvaging the crafted item gives back: half of the cost, rounded down.
+    pub fn salvage_yield(&self) -> BTreeMap<String, u32> {
+        self.cost
+            .iter()
+            .map(|(material, &amount)| (material.clone(), amount / 2))
+            .filter(|&(_, amount)| amount > 0)
+            .collect()
+    }
+}
+
+/// Crafting materials the party owns, by material id.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct MaterialStash {
+    materials: BTreeMap<String, u32>,
+}
+
+impl MaterialStash {
+    pub fn count(&self, material: &str) -> u32 {
+        self.materials.get(material).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, material: &str, amount: u32) {
+        if amount > 0 {
+            *self.materials.entry(material.to_string()).or_insert(0) += amount;
         }
-        for (k, v) in &self.cost {
-            *materials.get_mut(k).unwrap() -= v;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u32)> {
+        self.materials.iter()
+    }
+
+    /// Materials still needed to pay `cost`, with the missing amount of each.
+    pub fn missing(&self, cost: &BTreeMap<String, u32>) -> Vec<(String, u32)> {
+        cost.iter()
+            .filter(|(m, &n)| self.count(m) < n)
+            .map(|(m, &n)| (m.clone(), n - self.count(m)))
+            .collect()
+    }
+
+    /// Takes the whole cost out of the stash, or nothing if anything is missing.
+    pub fn spend(&mut self, cost: &BTreeMap<String, u32>) -> Result<(), Vec<(String, u32)>> {
+        let missing = self.missing(cost);
+        if !missing.is_empty() {
+            return Err(missing);
         }
-        Some(self.result.clone())
+        for (material, &amount) in cost {
+            let left = self.count(material) - amount;
+            if left == 0 {
+                self.materials.remove(material);
+            } else {
+                self.materials.insert(material.clone(), left);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a blueprint can be crafted from the current stash.
+#[derive(Debug, Clone)]
+pub struct CraftStatus {
+    pub blueprint_id: String,
+    /// Empty when the blueprint can be crafted right now.
+    pub missing: Vec<(String, u32)>,
+}
+
+impl CraftStatus {
+    pub fn craftable(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(materials: &[(&str, u32)]) -> BTreeMap<String, u32> {
+        materials.iter().map(|&(m, n)| (m.to_string(), n)).collect()
+    }
+
+    #[test]
+    fn spending_is_all_or_nothing() {
+        let mut stash = MaterialStash::default();
+        stash.add("iron_ore", 3);
+        stash.add("leather", 1);
+
+        let expensive = cost(&[("iron_ore", 2), ("leather", 2)]);
+        assert_eq!(
+            stash.spend(&expensive),
+            Err(vec![("leather".to_string(), 1)])
+        );
+        assert_eq!((stash.count("iron_ore"), stash.count("leather")), (3, 1));
+
+        stash
+            .spend(&cost(&[("iron_ore", 2), ("leather", 1)]))
+            .unwrap();
+        assert_eq!((stash.count("iron_ore"), stash.count("leather")), (1, 0));
+        assert_eq!(stash.iter().count(), 1);
     }
 }
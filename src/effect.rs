@@ -5,3 +5,22 @@ pub enum Effect {
     ReduceMovement(i32, i32), // amount, duration in turns
     Poison(i32, i32),         // damage per turn, duration in turns
 }
+
+impl Effect {
+    pub fn remaining_turns(&self) -> i32 {
+        match self {
+            Effect::ReduceMovement(_, turns) | Effect::Poison(_, turns) => *turns,
+        }
+    }
+
+    /// Counts the duration down by one turn.
+    pub fn tick(&mut self) {
+        match self {
+            Effect::ReduceMovement(_, turns) | Effect::Poison(_, turns) => *turns -= 1,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining_turns() <= 0
+    }
+}
@@ -1,26 +1,79 @@
+use crate::adventure::{AdventureMap, NodeKind};
+use crate::ai::Difficulty;
 pub use crate::assets::Assets;
-pub use crate::battlestate::BattleState;
+use crate::battlemap::BattleMap;
+pub use crate::battlestate::{BattleResult, BattleState};
 pub use crate::character::*;
-pub use crate::hexgrid::Hex;
+use crate::content::ContentRegistry;
+use crate::crafting::{CraftStatus, MaterialStash};
 pub use crate::inventory::*;
-use std::collections::HashMap;
+use crate::item::{Item, ItemSlot};
+use crate::loot::{BattleRewards, Loot};
+use crate::replay::{BattleLog, BattleReplay};
+use anyhow::anyhow;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
 pub enum Screen {
     #[default]
     Menu,
     Battle,
     Victory,
     Defeat,
+    Replay,
+    Party,
+    Workshop,
+    Map,
+}
+
+/// What the party screen is showing: the selected hero and the item picked up for moving.
+#[derive(Default)]
+pub struct PartyView {
+    pub hero: usize,
+    pub held: Option<ItemLocation>,
+    /// Shows the hero's skill tree instead of backpack and storage.
+    pub skills: bool,
+    /// Screen to go back to.
+    pub return_to: Screen,
+}
+
+/// What the workshop screen is showing: the selected blueprint.
+#[derive(Default)]
+pub struct WorkshopView {
+    pub blueprint: Option<String>,
+    /// Screen to go back to.
+    pub return_to: Screen,
+}
+
+/// How far the campaign has come.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Progress {
+    pub completed_encounters: Vec<String>,
+    /// Adventure map with the nodes visited so far.
+    pub map: Option<AdventureMap>,
 }
 
 #[derive(Default)]
 pub struct GameState {
     pub battle: Option<BattleState>,
+    pub replay: Option<BattleReplay>,
     pub player_party: Vec<Hero>,
     pub storage: Storage,
+    pub materials: MaterialStash,
     pub current_screen: Screen,
+    pub progress: Progress,
+    /// Encounter the current battle was started from.
+    pub current_encounter: Option<String>,
+    pub party_view: PartyView,
+    pub workshop_view: WorkshopView,
+    /// Rewards of the last won battle.
+    pub rewards: Option<BattleRewards>,
+    /// Id for the next item created, unique across the whole campaign.
+    pub next_item_id: u64,
+    pub difficulty: Difficulty,
     pub assets: Option<Assets>,
+    pub content: ContentRegistry,
 }
 
 impl GameState {
@@ -33,53 +86,767 @@ impl GameState {
 }
 
 impl GameState {
-    pub fn start_battle(&mut self) {
-        let goblin_stats = Stats {
-            max_hp: 30,
-            hp: 30,
-            damage: (5, 10),
-            attack: 12,
-            defense: 7,
-            initiative: 15,
-            movement: 3,
-        };
-        let orc_stats = Stats {
-            max_hp: 60,
-            hp: 45,
-            damage: (15, 20),
-            attack: 15,
-            defense: 2,
-            initiative: 5,
-            movement: 2,
+    /// Fresh campaign with the starting party from the loaded content.
+    pub fn new_campaign(&mut self) -> anyhow::Result<()> {
+        self.player_party = self.content.starting_party()?;
+        self.storage = Storage::default();
+        self.materials = MaterialStash::default();
+        self.progress = Progress::default();
+        self.rewards = None;
+        self.next_item_id = self.highest_item_id() + 1;
+        self.new_map(thread_rng().gen());
+        Ok(())
+    }
+
+    pub fn new_map(&mut self, seed: u64) {
+        self.progress.map = Some(AdventureMap::generate(&self.content.campaign, seed));
+    }
+
+    pub fn highest_item_id(&self) -> u64 {
+        self.player_party
+            .iter()
+            .flat_map(|h| {
+                h.inventory
+                    .backpack
+                    .iter()
+                    .chain(h.inventory.equipped.values())
+            })
+            .chain(&self.storage.items)
+            .map(|item| item.id)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn allocate_item_id(&mut self) -> u64 {
+        let id = self.next_item_id;
+        self.next_item_id += 1;
+        id
+    }
+
+    /// Moves the party to a node of the adventure map and does what the node does. Battle nodes
+    /// start their encounter, crafting camps open the workshop. Nothing changes if the node
+    /// can't be reached or its battle can't be started.
+    pub fn visit_node(&mut self, id: usize) -> anyhow::Result<()> {
+        let map = self
+            .progress
+            .map
+            .as_ref()
+            .ok_or_else(|| anyhow!("no adventure map"))?;
+        let node = map.reachable(id)?.clone();
+
+        match node.kind {
+            NodeKind::Battle | NodeKind::EliteBattle | NodeKind::Boss => {
+                let encounter = node
+                    .encounter
+                    .ok_or_else(|| anyhow!("battle node {} has no encounter", id))?;
+                self.start_encounter(&encounter)?;
+                self.current_screen = Screen::Battle;
+            }
+            NodeKind::Shrine => {
+                for hero in &mut self.player_party {
+                    hero.stats.hp = hero.effective_stats().max_hp;
+                }
+            }
+            NodeKind::Merchant => {
+                if let Some(template) = &node.item {
+                    let item = self.content.create_item(template, self.next_item_id)?;
+                    self.allocate_item_id();
+                    println!("The merchant hands over {}", item.name);
+                    self.stash_item(item);
+                }
+            }
+            NodeKind::CraftingCamp => {
+                self.workshop_view = WorkshopView {
+                    return_to: Screen::Map,
+                    ..WorkshopView::default()
+                };
+                self.current_screen = Screen::Workshop;
+            }
+        }
+
+        if let Some(map) = &mut self.progress.map {
+            map.path.push(id);
+        }
+        Ok(())
+    }
+
+    pub fn start_encounter(&mut self, encounter_id: &str) -> anyhow::Result<()> {
+        let encounter = self.content.encounter(encounter_id)?;
+        let map_id = encounter.map.clone();
+        let enemies = self.content.encounter_enemies(encounter)?;
+        self.start_battle(&map_id, &enemies)?;
+        self.current_encounter = Some(encounter_id.to_string());
+        Ok(())
+    }
+
+    pub fn start_battle(&mut self, map_id: &str, enemies: &[Enemy]) -> anyhow::Result<()> {
+        let map = BattleMap::load(map_id)?;
+        let seed = thread_rng().gen();
+
+        let enemies: Vec<Enemy> = enemies
+            .iter()
+            .cloned()
+            .map(|mut enemy| {
+                self.difficulty.scale_stats(&mut enemy.stats);
+                enemy
+            })
+            .collect();
+
+        self.battle = Some(
+            BattleState::new(&self.player_party, &enemies, &map, seed)?
+                .with_difficulty(self.difficulty),
+        );
+        self.current_encounter = None;
+        self.rewards = None;
+        Ok(())
+    }
+
+    /// Closes a decided battle and moves to its result screen. Items used up in the battle are
+    /// gone from the backpacks whatever the result. After a victory the party keeps
+    /// the HP it ended with and every survivor gets the XP of all defeated enemies. Fallen
+    /// heroes get back up with 1 HP and no XP.
+    pub fn finish_battle(&mut self) {
+        let result = match self.battle.as_ref().and_then(|b| b.result) {
+            Some(result) => result,
+            None => return,
         };
+        let battle = self.battle.take().unwrap();
+
+        for hero in &mut self.player_party {
+            hero.inventory
+                .backpack
+                .retain(|item| !battle.consumed_items.contains(&item.id));
+        }
+
+        match result {
+            BattleResult::Victory => {
+                let mut rewards = BattleRewards {
+                    xp: battle.xp_earned,
+                    ..Default::default()
+                };
+                for hero in &mut self.player_party {
+                    match battle.heroes.get(&hero.id) {
+                        Some(instance) => {
+                            let max_hp = hero.effective_stats().max_hp;
+                            hero.stats.hp = instance.stats.hp.clamp(1, max_hp);
+                            if hero.gain_xp(battle.xp_earned) > 0 {
+                                rewards.level_ups.push((hero.name.clone(), hero.level()));
+                            }
+                        }
+                        None => hero.stats.hp = 1,
+                    }
+                }
+                self.collect_loot(&battle.loot, &mut rewards);
+                self.rewards = Some(rewards);
+                if let Some(id) = self.current_encounter.take() {
+                    if !self.progress.completed_encounters.contains(&id) {
+                        self.progress.completed_encounters.push(id);
+                    }
+                }
+                self.current_screen = Screen::Victory;
+            }
+            BattleResult::Defeat => self.current_screen = Screen::Defeat,
+        }
+    }
+}
+
+impl GameState {
+    /// Puts a new item where the party keeps it: consumables in the first hero's backpack,
+    /// everything else in storage.
+    fn stash_item(&mut self, item: Item) {
+        match self.player_party.first_mut() {
+            Some(hero) if item.consumable.is_some() => hero.inventory.add_to_backpack(item),
+            _ => self.storage.push_item(item),
+        }
+    }
+
+    /// Turns dropped loot into items and materials, see `stash_item` for where items go.
+    fn collect_loot(&mut self, loot: &Loot, rewards: &mut BattleRewards) {
+        for template in &loot.items {
+            let id = self.allocate_item_id();
+            let item = match self.content.create_item(template, id) {
+                Ok(item) => item,
+                Err(e) => {
+                    eprintln!("Could not create loot: {:#}", e);
+                    continue;
+                }
+            };
+            rewards.items.push(item.clone());
+            self.stash_item(item);
+        }
+
+        for (material, &amount) in &loot.materials {
+            self.materials.add(material, amount);
+            *rewards.materials.entry(material.clone()).or_insert(0) += amount;
+        }
+    }
+}
+
+impl GameState {
+    /// Every blueprint with what the stash is still missing for it, sorted by id.
+    pub fn craft_status(&self) -> Vec<CraftStatus> {
+        let mut status: Vec<_> = self
+            .content
+            .blueprints
+            .iter()
+            .map(|(id, blueprint)| CraftStatus {
+                blueprint_id: id.clone(),
+                missing: self.materials.missing(&blueprint.cost),
+            })
+            .collect();
+        status.sort_by(|a, b| a.blueprint_id.cmp(&b.blueprint_id));
+        status
+    }
+
+    /// Spends the blueprint's materials and puts the new item into storage. Nothing changes if
+    /// the blueprint can't be crafted.
+    pub fn craft(&mut self, blueprint_id: &str) -> anyhow::Result<Item> {
+        let blueprint = self.content.blueprint(blueprint_id)?;
+        let item = self
+            .content
+            .create_item(&blueprint.result, self.next_item_id)?;
+
+        if let Err(missing) = self.materials.spend(&blueprint.cost) {
+            let missing: Vec<_> = missing
+                .iter()
+                .map(|(m, n)| format!("{} x{}", m, n))
+                .collect();
+            return Err(anyhow!(
+                "not enough materials for {}, missing {}",
+                item.name,
+                missing.join(", ")
+            ));
+        }
+
+        self.allocate_item_id();
+        self.storage.push_item(item.clone());
+        Ok(item)
+    }
+}
+
+impl GameState {
+    pub fn item_at(&self, location: ItemLocation) -> Option<&Item> {
+        match location {
+            ItemLocation::Storage(idx) => self.storage.items.get(idx),
+            ItemLocation::Backpack(hero, idx) => self
+                .player_party
+                .get(hero)
+                .and_then(|h| h.inventory.backpack.get(idx)),
+            ItemLocation::Equipped(hero, slot) => self
+                .player_party
+                .get(hero)
+                .and_then(|h| h.inventory.equipped.get(&slot)),
+        }
+    }
+
+    /// Every item the party owns with where it is: storage first, then each hero's backpack and
+    /// equipment.
+    pub fn owned_items(&self) -> Vec<(ItemLocation, &Item)> {
+        let mut items: Vec<_> = self
+            .storage
+            .items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (ItemLocation::Storage(idx), item))
+            .collect();
+        for (hero_idx, hero) in self.player_party.iter().enumerate() {
+            items.extend(
+                hero.inventory
+                    .backpack
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, item)| (ItemLocation::Backpack(hero_idx, idx), item)),
+            );
+            items.extend(
+                ItemSlot::ALL
+                    .iter()
+                    .filter_map(|&slot| Some((slot, hero.inventory.equipped.get(&slot)?)))
+                    .map(|(slot, item)| (ItemLocation::Equipped(hero_idx, slot), item)),
+            );
+        }
+        items
+    }
+
+    pub fn take_item(&mut self, location: ItemLocation) -> Option<Item> {
+        match location {
+            ItemLocation::Storage(idx) => self.storage.pull_item(idx),
+            ItemLocation::Backpack(hero, idx) => self
+                .player_party
+                .get_mut(hero)
+                .and_then(|h| h.inventory.remove_from_backpack(idx)),
+            ItemLocation::Equipped(hero, slot) => self
+                .player_party
+                .get_mut(hero)
+                .and_then(|h| h.inventory.unequip(slot)),
+        }
+    }
+
+    /// Spends a party member's skill points on a node of their class tree.
+    pub fn unlock_skill(&mut self, hero_idx: usize, node_id: &str) -> anyhow::Result<()> {
+        let hero = self
+            .player_party
+            .get_mut(hero_idx)
+            .ok_or_else(|| anyhow!("no hero {}", hero_idx))?;
+        self.content.unlock_skill(hero, node_id)
+    }
+
+    /// Moves an item between storage, backpacks and equipment slots. Whatever was in the
+    /// target slot goes to where the item came from, or to storage if it came from a slot.
+    pub fn move_item(&mut self, from: ItemLocation, to: ItemDestination) -> anyhow::Result<()> {
+        let item = self
+            .item_at(from)
+            .ok_or_else(|| anyhow!("no item at {:?}", from))?;
+
+        if let ItemDestination::Backpack(hero) | ItemDestination::Equip(hero, _) = to {
+            if hero >= self.player_party.len() {
+                return Err(anyhow!("no hero {}", hero));
+            }
+        }
+        if let ItemDestination::Equip(_, slot) = to {
+            if item.slot != Some(slot) {
+                return Err(anyhow!("{} can't be worn as {:?}", item.name, slot));
+            }
+        }
+
+        let item = self.take_item(from).unwrap();
+        match to {
+            ItemDestination::Storage => self.storage.push_item(item),
+            ItemDestination::Backpack(hero) => {
+                self.player_party[hero].inventory.add_to_backpack(item)
+            }
+            ItemDestination::Equip(hero, slot) => {
+                let replaced = self.player_party[hero]
+                    .inventory
+                    .equip(slot, item)
+                    .map_err(|item| anyhow!("{} can't be worn as {:?}", item.name, slot))?;
+                if let Some(replaced) = replaced {
+                    match from {
+                        ItemLocation::Backpack(hero, _) => {
+                            self.player_party[hero].inventory.add_to_backpack(replaced)
+                        }
+                        ItemLocation::Storage(_) | ItemLocation::Equipped(..) => {
+                            self.storage.push_item(replaced)
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Destroys the item and adds its salvage yield to the material stash.
+    pub fn salvage(&mut self, location: ItemLocation) -> anyhow::Result<Vec<(String, u32)>> {
+        let item = self
+            .item_at(location)
+            .ok_or_else(|| anyhow!("no item at {:?}", location))?;
+        let materials = item.decompose();
+        if materials.is_empty() {
+            return Err(anyhow!("{} can't be salvaged", item.name));
+        }
+
+        self.take_item(location);
+        for (material, amount) in &materials {
+            self.materials.add(material, *amount);
+        }
+        Ok(materials)
+    }
+}
+
+impl GameState {
+    pub fn load_replay(&mut self, path: &str) -> anyhow::Result<()> {
+        let log = BattleLog::load(path)?;
+        self.replay = Some(BattleReplay::new(log)?);
+        self.current_screen = Screen::Replay;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item::Stat;
+    use crate::testing::item;
+
+    fn new_game() -> GameState {
+        let mut state = GameState::new();
+        state.content = ContentRegistry::load().unwrap();
+        state.new_campaign().unwrap();
+        state
+    }
+
+    #[test]
+    fn party_members_unlock_skills_with_their_points() {
+        let mut state = new_game();
+        state.player_party[0].skill_points = 1;
+        let max_hp = state.player_party[0].stats.max_hp;
+
+        assert!(state.unlock_skill(0, "iron_skin").is_err());
+        assert!(state.unlock_skill(5, "toughness").is_err());
+        state.unlock_skill(0, "toughness").unwrap();
+
+        let fighter = &state.player_party[0];
+        assert_eq!(fighter.unlocked_skills, ["toughness"]);
+        assert_eq!(fighter.skill_points, 0);
+        assert_eq!(fighter.stats.max_hp, max_hp + 15);
+        assert!(state.unlock_skill(0, "weapon_training").is_err());
+    }
+
+    #[test]
+    fn encounter_starts_battle_with_its_enemies() {
+        let mut state = new_game();
+        state.start_encounter("river_crossing").unwrap();
+
+        let battle = state.battle.as_ref().unwrap();
+        let mut names: Vec<_> = battle.enemies.values().map(|e| e.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["Goblin", "Goblin", "Orc"]);
+        assert_eq!(battle.log.setup.map.id, "river_crossing");
+    }
+
+    #[test]
+    fn victory_writes_hp_and_xp_back_to_the_party() {
+        let mut state = new_game();
+        state.start_encounter("goblin_ambush").unwrap();
+        let battle = state.battle.as_mut().unwrap();
+        battle.heroes.get_mut(&0).unwrap().stats.hp = 40;
+        battle.xp_earned = 120;
+        battle.result = Some(BattleResult::Victory);
+
+        state.finish_battle();
+
+        let fighter = &state.player_party[0];
+        assert!(state.battle.is_none());
+        assert!(state.current_screen == Screen::Victory);
+        assert_eq!(state.progress.completed_encounters, ["goblin_ambush"]);
+        assert_eq!((fighter.xp, fighter.level()), (120, 2));
+        assert_eq!((fighter.stats.hp, fighter.stats.max_hp), (50, 110));
+    }
+
+    #[test]
+    fn hp_from_equipment_survives_the_battle() {
+        let mut state = new_game();
+        let ring = item(90, ItemSlot::Accessory, vec![(Stat::MaxHp, 20)]);
+        state.player_party[0]
+            .inventory
+            .equip(ItemSlot::Accessory, ring)
+            .unwrap();
+        state.start_encounter("goblin_ambush").unwrap();
+        let battle = state.battle.as_mut().unwrap();
+        assert_eq!(battle.heroes[&0].stats.max_hp, 120);
+        battle.heroes.get_mut(&0).unwrap().stats.hp = 115;
+        battle.xp_earned = 120;
+        battle.result = Some(BattleResult::Victory);
+
+        state.finish_battle();
+
+        let fighter = &state.player_party[0];
+        assert_eq!(fighter.level(), 2);
+        assert_eq!(fighter.stats.hp, 125);
+        assert_eq!(fighter.effective_stats().max_hp, 130);
+    }
+
+    #[test]
+    fn battle_hp_is_written_back_by_hero_id() {
+        let mut state = new_game();
+        let mut second = state.player_party[0].clone();
+        second.id = 3;
+        second.name = "Second".to_string();
+        state.player_party[0].id = 5;
+        state.player_party.push(second);
+        state.start_encounter("goblin_ambush").unwrap();
+        let battle = state.battle.as_mut().unwrap();
+        let mut ids: Vec<_> = battle.heroes.keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, vec![3, 5]);
+        battle.heroes.get_mut(&5).unwrap().stats.hp = 40;
+        battle.heroes.get_mut(&3).unwrap().stats.hp = 60;
+        battle.result = Some(BattleResult::Victory);
+
+        state.finish_battle();
+
+        assert_eq!(state.player_party[0].stats.hp, 40);
+        assert_eq!(state.player_party[1].stats.hp, 60);
+    }
+
+    #[test]
+    fn victory_hands_out_loot() {
+        let mut state = new_game();
+        let backpack = state.player_party[0].inventory.backpack.len();
+        let first_id = state.next_item_id;
+        state.start_encounter("goblin_ambush").unwrap();
+        let battle = state.battle.as_mut().unwrap();
+        battle.loot.items = vec!["healing_potion".to_string(), "rusty_sword".to_string()];
+        battle.loot.materials.insert("iron_ore".to_string(), 2);
+        battle.result = Some(BattleResult::Victory);
+
+        state.finish_battle();
+
+        let rewards = state.rewards.as_ref().unwrap();
+        assert_eq!(rewards.items.len(), 2);
+        assert_eq!(rewards.materials["iron_ore"], 2);
+        assert_eq!(state.materials.count("iron_ore"), 2);
+        assert_eq!(state.storage.items[0].name, "Rusty Sword");
+        assert_eq!(state.player_party[0].inventory.backpack.len(), backpack + 1);
+        assert_eq!(state.next_item_id, first_id + 2);
+        assert!(first_id > state.player_party[0].inventory.backpack[0].id);
+    }
+
+    /// Puts the party right before the first node of the given kind.
+    fn next_to(state: &mut GameState, kind: NodeKind) -> usize {
+        for seed in 0.. {
+            state.new_map(seed);
+            let map = state.progress.map.as_mut().unwrap();
+            let Some(id) = map.nodes.iter().position(|n| n.kind == kind) else {
+                continue;
+            };
+            if let Some(before) = map.nodes.iter().position(|n| n.next.contains(&id)) {
+                map.path = vec![before];
+            }
+            return id;
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn battle_nodes_start_their_encounter() {
+        let mut state = new_game();
+        state.new_map(3);
+        let map = state.progress.map.as_ref().unwrap();
+        let first = map.choices()[0];
+        let encounter = map.nodes[first].encounter.clone();
+
+        state.visit_node(first).unwrap();
+
+        assert!(state.current_screen == Screen::Battle);
+        assert_eq!(state.current_encounter, encounter);
+        assert_eq!(state.progress.map.as_ref().unwrap().path, [first]);
+
+        state.battle.as_mut().unwrap().result = Some(BattleResult::Victory);
+        state.finish_battle();
+        assert_eq!(state.progress.completed_encounters, [encounter.unwrap()]);
+    }
+
+    #[test]
+    fn difficulty_applies_to_the_started_battle() {
+        let mut state = new_game();
+        state.difficulty = Difficulty::Hard;
+        state.start_encounter("goblin_ambush").unwrap();
+
+        let battle = state.battle.as_ref().unwrap();
+        let orc_hp = state.content.enemies["orc"].stats.max_hp;
+        assert_eq!(battle.difficulty, Difficulty::Hard);
+        assert_eq!(battle.log.setup.difficulty, Difficulty::Hard);
+        assert!(battle.enemies.values().any(|e| e.stats.max_hp > orc_hp));
+    }
+
+    #[test]
+    fn unreachable_nodes_cannot_be_visited() {
+        let mut state = new_game();
+        let boss = state.progress.map.as_ref().unwrap().nodes.len() - 1;
+
+        assert!(state.visit_node(boss).is_err());
+        assert!(state.battle.is_none());
+        assert!(state.progress.map.as_ref().unwrap().path.is_empty());
+    }
+
+    #[test]
+    fn shrines_heal_and_merchants_hand_out_items() {
+        let mut state = new_game();
+        state.player_party[0].stats.hp = 1;
+        let ring = item(90, ItemSlot::Accessory, vec![(Stat::MaxHp, 20)]);
+        state.player_party[0]
+            .inventory
+            .equip(ItemSlot::Accessory, ring)
+            .unwrap();
+        let shrine = next_to(&mut state, NodeKind::Shrine);
+        state.visit_node(shrine).unwrap();
+        let hero = &state.player_party[0];
+        assert_eq!(hero.stats.hp, hero.stats.max_hp + 20);
+
+        let merchant = next_to(&mut state, NodeKind::Merchant);
+        let owned = state.owned_items().len();
+        state.visit_node(merchant).unwrap();
+        assert_eq!(state.owned_items().len(), owned + 1);
+        assert!(state.current_screen == Screen::Menu);
+    }
+
+    #[test]
+    fn crafting_spends_materials_or_changes_nothing() {
+        let mut state = new_game();
+        let first_id = state.next_item_id;
+        state.materials.add("iron_ore", 4);
+
+        assert!(state.craft("rusty_sword").is_err());
+        assert_eq!(state.materials.count("iron_ore"), 4);
+        assert!(state.storage.items.is_empty());
+        let status = state.craft_status();
+        let sword = status
+            .iter()
+            .find(|s| s.blueprint_id == "rusty_sword")
+            .unwrap();
+        assert_eq!(sword.missing, [("wood".to_string(), 1)]);
+
+        state.materials.add("wood", 1);
+        let crafted = state.craft("rusty_sword").unwrap();
+
+        assert_eq!(crafted.id, first_id);
+        assert_eq!(state.next_item_id, first_id + 1);
+        assert_eq!(state.materials.count("iron_ore"), 1);
+        assert_eq!(state.materials.count("wood"), 0);
+        assert_eq!(state.storage.items[0].name, "Rusty Sword");
+        assert!(state.craft("no_such_blueprint").is_err());
+    }
+
+    #[test]
+    fn salvaging_credits_materials_in_one_step() {
+        let mut state = new_game();
+        let sword = state.content.create_item("rusty_sword", 100).unwrap();
+        let boots = state.content.create_item("orcish_boots", 101).unwrap();
+        state.storage.push_item(sword);
+        state.player_party[0].inventory.add_to_backpack(boots);
+        let backpack = state.player_party[0].inventory.backpack.len();
+
+        let from_storage = state.salvage(ItemLocation::Storage(0)).unwrap();
+        let from_backpack = state
+            .salvage(ItemLocation::Backpack(0, backpack - 1))
+            .unwrap();
+
+        assert_eq!(from_storage, [("iron_ore".to_string(), 1)]);
+        assert_eq!(from_backpack.len(), 2);
+        assert!(state.storage.items.is_empty());
+        assert_eq!(state.player_party[0].inventory.backpack.len(), backpack - 1);
+        assert_eq!(state.materials.count("iron_ore"), 1);
+        assert_eq!(state.materials.count("leather"), 1);
+
+        // Potions give nothing back and stay where they are.
+        assert!(state.salvage(ItemLocation::Backpack(0, 0)).is_err());
+        assert_eq!(state.player_party[0].inventory.backpack.len(), backpack - 1);
+        assert!(state.salvage(ItemLocation::Storage(5)).is_err());
+    }
+
+    #[test]
+    fn moving_items_equips_and_swaps() {
+        let mut state = new_game();
+        let old_sword = state.content.create_item("rusty_sword", 100).unwrap();
+        let new_sword = state.content.create_item("rusty_sword", 101).unwrap();
+        let boots = state.content.create_item("orcish_boots", 102).unwrap();
+        state.player_party[0].inventory.add_to_backpack(new_sword);
+        state.storage.push_item(old_sword);
+        state.storage.push_item(boots);
+        let backpack = state.player_party[0].inventory.backpack.len();
+
+        state
+            .move_item(
+                ItemLocation::Storage(0),
+                ItemDestination::Equip(0, ItemSlot::MainHand),
+            )
+            .unwrap();
+        state
+            .move_item(
+                ItemLocation::Backpack(0, backpack - 1),
+                ItemDestination::Equip(0, ItemSlot::MainHand),
+            )
+            .unwrap();
+
+        // The replaced sword goes back into the backpack the new one came from.
+        let inventory = &state.player_party[0].inventory;
+        assert_eq!(inventory.equipped[&ItemSlot::MainHand].id, 101);
+        assert_eq!(inventory.backpack.len(), backpack);
+        assert_eq!(inventory.backpack[backpack - 1].id, 100);
+
+        state
+            .move_item(ItemLocation::Storage(0), ItemDestination::Backpack(0))
+            .unwrap();
+        assert!(state.storage.items.is_empty());
+        assert_eq!(state.player_party[0].inventory.backpack[backpack].id, 102);
+    }
+
+    #[test]
+    fn moving_into_the_wrong_slot_changes_nothing() {
+        let mut state = new_game();
+        let boots = state.content.create_item("orcish_boots", 100).unwrap();
+        state.storage.push_item(boots);
+
+        assert!(state
+            .move_item(
+                ItemLocation::Storage(0),
+                ItemDestination::Equip(0, ItemSlot::Head)
+            )
+            .is_err());
+        assert!(state
+            .move_item(ItemLocation::Storage(0), ItemDestination::Backpack(7))
+            .is_err());
+        assert!(state
+            .move_item(ItemLocation::Storage(3), ItemDestination::Backpack(0))
+            .is_err());
+
+        assert_eq!(state.storage.items.len(), 1);
+        assert!(state.player_party[0].inventory.equipped.is_empty());
+    }
+
+    #[test]
+    fn owned_items_covers_storage_backpacks_and_equipment() {
+        let mut state = new_game();
+        let sword = state.content.create_item("rusty_sword", 100).unwrap();
+        let cap = state.content.create_item("leather_cap", 101).unwrap();
+        state.storage.push_item(sword);
+        state.player_party[0]
+            .inventory
+            .equip(ItemSlot::Head, cap)
+            .unwrap();
+        let backpack = state.player_party[0].inventory.backpack.len();
+
+        let owned = state.owned_items();
+
+        assert_eq!(owned.len(), backpack + 2);
+        assert_eq!(owned[0].0, ItemLocation::Storage(0));
+        assert_eq!(owned[1].0, ItemLocation::Backpack(0, 0));
+        assert_eq!(
+            owned[backpack + 1].0,
+            ItemLocation::Equipped(0, ItemSlot::Head)
+        );
+        for (location, item) in owned {
+            assert_eq!(state.item_at(location).unwrap().id, item.id);
+        }
+    }
+
+    #[test]
+    fn used_items_are_gone_after_the_battle() {
+        let mut state = new_game();
+        let backpack = state.player_party[0].inventory.backpack.len();
+        let used = state.player_party[0].inventory.backpack[0].id;
+        state.start_encounter("goblin_ambush").unwrap();
+        let battle = state.battle.as_mut().unwrap();
+        battle.consumed_items.push(used);
+        battle.result = Some(BattleResult::Defeat);
+
+        state.finish_battle();
+
+        let backpack_after = &state.player_party[0].inventory.backpack;
+        assert_eq!(backpack_after.len(), backpack - 1);
+        assert!(backpack_after.iter().all(|item| item.id != used));
+    }
+
+    #[test]
+    fn fallen_heroes_get_up_with_one_hp_and_no_xp() {
+        let mut state = new_game();
+        state.start_encounter("goblin_ambush").unwrap();
+        let battle = state.battle.as_mut().unwrap();
+        battle.heroes.clear();
+        battle.xp_earned = 120;
+        battle.result = Some(BattleResult::Victory);
+
+        state.finish_battle();
+
+        assert_eq!(state.player_party[0].stats.hp, 1);
+        assert_eq!(state.player_party[0].xp, 0);
+    }
 
-        let enemies: HashMap<i32, Enemy> = HashMap::from([
-            (
-                0,
-                Enemy {
-                    id: 0,
-                    name: "Goblin".to_string(),
-                    hex: Hex { q: 7, r: 5 }, // for testing
-                    stats: goblin_stats,
-                    effects: Vec::new(),
-                },
-            ),
-            (
-                1,
-                Enemy {
-                    id: 1,
-                    name: "Orc".to_string(),
-                    hex: Hex { q: 1, r: 6 }, // for testing
-                    stats: orc_stats,
-                    effects: Vec::new(),
-                },
-            ),
-        ]);
-
-        let heroes = &self.player_party;
-        let enemy_vec: &Vec<Enemy> = &enemies.into_values().collect();
-        let assets = self.assets.clone();
-
-        self.battle = Some(BattleState::new(heroes, enemy_vec, assets.unwrap()));
+    #[test]
+    fn unknown_encounter_or_map_is_an_error() {
+        let mut state = new_game();
+        assert!(state.start_encounter("no_such_encounter").is_err());
+        assert!(state.start_battle("no_such_map", &[]).is_err());
+        assert!(state.battle.is_none());
     }
 }
@@ -1,12 +1,87 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Shifts lines a hair off the exact centre line, so that a line running along the edge between
+/// two hexes lands on one of them instead of flickering between both.
+const LINE_NUDGE: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Hex {
     pub q: i32,
     pub r: i32,
 }
 
 impl Hex {
+    /// Cube coordinates for the "odd-q" layout used by the grid (odd columns are shifted down).
+    pub fn to_cube(self) -> (i32, i32, i32) {
+        let x = self.q;
+        let z = self.r - (self.q - (self.q & 1)) / 2;
+        (x, -x - z, z)
+    }
+
+    fn from_cube(x: i32, z: i32) -> Hex {
+        Hex {
+            q: x,
+            r: z + (x - (x & 1)) / 2,
+        }
+    }
+
+    /// Number of steps between two hexes, ignoring terrain.
+    pub fn distance(self, other: Hex) -> i32 {
+        let (ax, ay, az) = self.to_cube();
+        let (bx, by, bz) = other.to_cube();
+        (ax - bx).abs().max((ay - by).abs()).max((az - bz).abs())
+    }
+
+    /// Whether nothing on the line between the two hexes blocks it. The ends themselves don't
+    /// count. A line running exactly along the edge between two hexes is clear if either side
+    /// is, so sight works the same in both directions.
+    pub fn has_line_of_sight(self, other: Hex, blocks: impl Fn(Hex) -> bool) -> bool {
+        [LINE_NUDGE, -LINE_NUDGE].into_iter().any(|nudge| {
+            let line = self.line_nudged(other, nudge);
+            let between = line.len().saturating_sub(2);
+            line.into_iter()
+                .skip(1)
+                .take(between)
+                .all(|hex| !blocks(hex))
+        })
+    }
+
+    /// Hexes crossed by a straight line between the centres of the two hexes, both ends
+    /// included, in order, with the line shifted by `nudge`.
+    fn line_nudged(self, other: Hex, nudge: f32) -> Vec<Hex> {
+        let steps = self.distance(other);
+        let (ax, ay, az) = self.to_cube();
+        let (bx, by, bz) = other.to_cube();
+        let start = (
+            ax as f32 + nudge,
+            ay as f32 + nudge,
+            az as f32 - 2.0 * nudge,
+        );
+        let end = (
+            bx as f32 + nudge,
+            by as f32 + nudge,
+            bz as f32 - 2.0 * nudge,
+        );
+
+        (0..=steps)
+            .map(|i| {
+                let t = if steps == 0 {
+                    0.0
+                } else {
+                    i as f32 / steps as f32
+                };
+                let lerp = |a: f32, b: f32| a + (b - a) * t;
+                cube_round(
+                    lerp(start.0, end.0),
+                    lerp(start.1, end.1),
+                    lerp(start.2, end.2),
+                )
+            })
+            .collect()
+    }
+
+    // Pathfinding has its own copy in `pathfinding::hex_neighbors`.
+    #[allow(dead_code)]
     pub fn neighbors(&self, grid_width: i32, grid_height: i32) -> Vec<Hex> {
         const DIRECTIONS_EVEN: [(i32, i32); 6] =
             [(0, -1), (1, -1), (1, 0), (0, 1), (-1, 0), (-1, -1)];
@@ -29,6 +104,7 @@ impl Hex {
             .collect()
     }
 
+    #[allow(dead_code)]
     pub fn is_adjacent(&self, hex: Hex, grid_width: i32, grid_height: i32) -> bool {
         self.neighbors(grid_width, grid_height).contains(&hex)
     }
@@ -64,11 +140,10 @@ impl Hex {
                         && neighbor.q < grid_width
                         && neighbor.r >= 0
                         && neighbor.r < grid_height
+                        && !visited.contains(&(neighbor.q, neighbor.r))
                     {
-                        if !visited.contains(&(neighbor.q, neighbor.r)) {
-                            visited.insert((neighbor.q, neighbor.r));
-                            next_frontier.push(neighbor);
-                        }
+                        visited.insert((neighbor.q, neighbor.r));
+                        next_frontier.push(neighbor);
                     }
                 }
             }
@@ -78,3 +153,107 @@ impl Hex {
         area
     }
 }
+
+/// Nearest hex to a point in cube coordinates.
+fn cube_round(x: f32, y: f32, z: f32) -> Hex {
+    let (mut rx, ry, mut rz) = (x.round(), y.round(), z.round());
+    let (dx, dy, dz) = ((rx - x).abs(), (ry - y).abs(), (rz - z).abs());
+    // Rounding can break x + y + z == 0, so the coordinate that moved the most is rebuilt.
+    if dx > dy && dx > dz {
+        rx = -ry - rz;
+    } else if dy <= dz {
+        rz = -rx - ry;
+    }
+    Hex::from_cube(rx as i32, rz as i32)
+}
+
+/// Serializes a `HashMap<Hex, V>` as a list of pairs sorted by hex, since JSON only allows
+/// string keys. Use with `#[serde(with = "hex_keyed")]`.
+pub mod hex_keyed {
+    use super::Hex;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<V: Serialize, S: Serializer>(
+        map: &HashMap<Hex, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut pairs: Vec<_> = map.iter().collect();
+        pairs.sort_by_key(|(hex, _)| **hex);
+        pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, V: Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<Hex, V>, D::Error> {
+        let pairs: Vec<(Hex, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex;
+
+    #[test]
+    fn neighbors_are_one_step_away() {
+        for center in [hex!(4, 4), hex!(5, 4)] {
+            for n in center.neighbors(10, 10) {
+                assert_eq!(center.distance(n), 1, "{:?} -> {:?}", center, n);
+            }
+        }
+    }
+
+    #[test]
+    fn distance_follows_odd_columns() {
+        assert_eq!(hex!(1, 0).distance(hex!(2, 1)), 1);
+        assert_eq!(hex!(0, 0).distance(hex!(1, 1)), 2);
+        assert_eq!(hex!(0, 0).distance(hex!(0, 4)), 4);
+        assert_eq!(hex!(0, 0).distance(hex!(4, 0)), 4);
+    }
+
+    #[test]
+    fn lines_step_one_hex_at_a_time() {
+        for (a, b) in [
+            (hex!(0, 0), hex!(5, 3)),
+            (hex!(4, 4), hex!(1, 0)),
+            (hex!(2, 7), hex!(7, 2)),
+            (hex!(3, 3), hex!(3, 3)),
+        ] {
+            for nudge in [LINE_NUDGE, -LINE_NUDGE] {
+                let line = a.line_nudged(b, nudge);
+
+                assert_eq!(line.len() as i32, a.distance(b) + 1);
+                assert_eq!(line.first(), Some(&a));
+                assert_eq!(line.last(), Some(&b));
+                for pair in line.windows(2) {
+                    assert_eq!(pair[0].distance(pair[1]), 1, "{:?}", line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sight_is_blocked_by_hexes_between_the_ends_only() {
+        let (from, to) = (hex!(0, 2), hex!(4, 2));
+        let wall = |hex: Hex| hex.q == 2;
+
+        assert!(!from.has_line_of_sight(to, wall));
+        assert!(!to.has_line_of_sight(from, wall));
+        assert!(from.has_line_of_sight(hex!(2, 2), wall));
+        assert!(from.has_line_of_sight(to, |hex| hex == from || hex == to));
+    }
+
+    #[test]
+    fn lines_along_an_edge_are_clear_if_either_side_is() {
+        // The line from (0,2) to (2,2) runs between (1,1) and (1,2).
+        let (from, to) = (hex!(0, 2), hex!(2, 2));
+        assert_eq!(from.distance(to), 2);
+
+        assert!(from.has_line_of_sight(to, |hex| hex == hex!(1, 1)));
+        assert!(from.has_line_of_sight(to, |hex| hex == hex!(1, 2)));
+        assert!(to.has_line_of_sight(from, |hex| hex == hex!(1, 1)));
+        assert!(!from.has_line_of_sight(to, |hex| hex.q == 1));
+    }
+}
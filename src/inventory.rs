@@ -9,7 +9,6 @@ pub struct Inventory {
     pub backpack: Vec<Item>,
 }
 
-// Unused yet
 impl Inventory {
     pub fn new() -> Self {
         Self {
@@ -18,8 +17,14 @@ impl Inventory {
         }
     }
 
-    pub fn equip(&mut self, slot: ItemSlot, item: Item) -> Option<Item> {
-        self.equipped.insert(slot, item)
+    /// Puts the item into `slot` and returns whatever was there before. An item that doesn't
+    /// belong in that slot is handed back as the error.
+    #[allow(clippy::result_large_err)] // The rejected item goes back to the caller.
+    pub fn equip(&mut self, slot: ItemSlot, item: Item) -> Result<Option<Item>, Item> {
+        if item.slot != Some(slot) {
+            return Err(item);
+        }
+        Ok(self.equipped.insert(slot, item))
     }
 
     pub fn unequip(&mut self, slot: ItemSlot) -> Option<Item> {
@@ -39,7 +44,24 @@ impl Inventory {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+/// Where an item the party owns currently is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemLocation {
+    Storage(usize),
+    /// Hero index in the party and index in their backpack.
+    Backpack(usize, usize),
+    Equipped(usize, ItemSlot),
+}
+
+/// Where an item can be moved to. Containers take the item at the end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemDestination {
+    Storage,
+    Backpack(usize),
+    Equip(usize, ItemSlot),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Storage {
     pub items: Vec<Item>,
 }
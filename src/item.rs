@@ -1,8 +1,8 @@
 use crate::effect::Effect;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-// Unused yet
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ItemSlot {
     Head,
     Body,
@@ -12,18 +12,81 @@ pub enum ItemSlot {
     Accessory,
 }
 
+impl ItemSlot {
+    pub const ALL: [ItemSlot; 6] = [
+        ItemSlot::Head,
+        ItemSlot::Body,
+        ItemSlot::Boots,
+        ItemSlot::MainHand,
+        ItemSlot::OffHand,
+        ItemSlot::Accessory,
+    ];
+}
+
+/// Stat an item can raise or lower. `Damage` shifts both ends of the damage range.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stat {
+    MaxHp,
+    Damage,
+    Attack,
+    Defense,
+    Initiative,
+    Movement,
+}
+
+/// What an item does when used in battle. Using one costs the hero's action.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Consumable {
+    /// Restores HP to the user or an adjacent ally.
+    Heal(i32),
+    /// Cures poison on the user or an adjacent ally.
+    Antidote,
+    /// Thrown at an enemy up to `range` hexes away.
+    Throwable {
+        range: i32,
+        damage: i32,
+        effect: Option<Effect>,
+    },
+}
+
+impl Consumable {
+    pub fn range(&self) -> i32 {
+        match self {
+            Consumable::Throwable { range, .. } => *range,
+            Consumable::Heal(_) | Consumable::Antidote => 1,
+        }
+    }
+
+    pub fn targets_allies(&self) -> bool {
+        !matches!(self, Consumable::Throwable { .. })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Item {
+    /// Unique per item instance. Templates in `data/content/items.json` leave it out.
+    #[serde(default)]
     pub id: u64,
     pub name: String,
     pub slot: Option<ItemSlot>,
-    pub stat_changes: Vec<(String, i32)>,
+    #[serde(default)]
+    pub stat_changes: Vec<(Stat, i32)>,
+    /// Applied to the target whenever the wearer hits something.
+    #[serde(default)]
     pub effects: Vec<Effect>,
-    // pub salvaged_materials: Vec<Material>,
+    #[serde(default)]
+    pub consumable: Option<Consumable>,
+    /// Materials returned by salvaging the item. Templates that leave it out get part of the
+    /// cost of the blueprint that crafts them, see `Blueprint::salvage_yield`.
+    #[serde(default)]
+    pub salvage: BTreeMap<String, u32>,
 }
 
 impl Item {
     pub fn decompose(&self) -> Vec<(String, u32)> {
-        vec![("iron_ore".to_string(), 2)]
+        self.salvage
+            .iter()
+            .map(|(material, &amount)| (material.clone(), amount))
+            .collect()
     }
 }
@@ -0,0 +1,39 @@
+use crate::item::Item;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum LootDrop {
+    /// Item template id from `data/content/items.json`.
+    Item(String),
+    Material {
+        id: String,
+        min: u32,
+        max: u32,
+    },
+}
+
+/// One line of an enemy loot table, dropping with the given chance (0.0 to 1.0).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LootEntry {
+    pub chance: f64,
+    pub drop: LootDrop,
+}
+
+/// Loot collected during a battle, handed out after a victory.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Loot {
+    /// Item template ids.
+    pub items: Vec<String>,
+    pub materials: BTreeMap<String, u32>,
+}
+
+/// What the party got out of a won battle, listed on the rewards screen.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BattleRewards {
+    pub xp: u64,
+    pub items: Vec<Item>,
+    pub materials: BTreeMap<String, u32>,
+    /// Hero name and the level they reached.
+    pub level_ups: Vec<(String, u32)>,
+}
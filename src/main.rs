@@ -1,75 +1,62 @@
+mod adventure;
 mod ai;
 mod assets;
 mod battlefield;
+mod battlemap;
 mod battlestate;
 mod button;
 mod character;
+mod content;
 mod crafting;
 mod effect;
 mod gamestate;
 mod hexgrid;
 mod inventory;
 mod item;
+mod loot;
 mod macros;
 mod pathfinding;
+mod replay;
+mod save;
+mod simulate;
+mod skilltree;
+#[cfg(test)]
+mod testing;
 mod ui;
 
 use crate::assets::Assets;
-use crate::character::*;
-use crate::effect::Effect;
-use crate::hexgrid::Hex;
-use crate::inventory::Inventory;
+use crate::content::ContentRegistry;
 use gamestate::GameState;
 
-#[macroquad::main("BigTask")]
-async fn main() {
-    let mut state = GameState::new();
-
-    let assets = Assets::load().await;
-
-    let fighter_stats = Stats {
-        max_hp: 100,
-        hp: 75,
-        damage: (10, 16),
-        attack: 16,
-        defense: 11,
-        initiative: 12,
-        movement: 2,
-    };
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("simulate") {
+        if let Err(e) = simulate::run(&args[1..]) {
+            eprintln!("Simulation failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    macroquad::Window::new("BigTask", run_game());
+}
 
-    let melee = Ability {
-        id: 0,
-        name: "Slash".to_string(),
-        description: "A powerful melee attack.".to_string(),
-        damage_modifier: 1.5,
-        effect: None,
-        range: 1,
-    };
+async fn run_game() {
+    let mut state = GameState::new();
 
-    let ranged = Ability {
-        id: 1,
-        name: "Bola Throw".to_string(),
-        description:
-            "Throw a bola to entangle the target, reducing their movement. Range: 3 hexes."
-                .to_string(),
-        damage_modifier: 0.6,
-        effect: Some(Effect::ReduceMovement(1, 2)), // reduce 1 movement for 2 turns
-        range: 3,
+    state.content = match ContentRegistry::load() {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Could not load game content: {:#}", e);
+            return;
+        }
     };
+    if let Err(e) = state.new_campaign() {
+        eprintln!("Could not create the starting party: {:#}", e);
+        return;
+    }
 
-    let abilities = vec![melee, ranged];
-
-    state.player_party.push(Hero {
-        id: 0,
-        name: "Fighter".to_string(),
-        hex: Hex { q: 2, r: 3 },
-        stats: fighter_stats,
-        xp: 0,
-        abilities,
-        inventory: Inventory::new(),
-        effects: Vec::new(),
-    });
-
+    let assets = Assets::load().await;
     state.assets = Some(assets);
 
     ui::run(&mut state).await;
@@ -1,46 +1,47 @@
 use crate::battlestate::BattleState;
 use crate::hexgrid::Hex;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 
 // Return a map from reachable Hex -> (cost, path).
 // Paths include the start as the first element and the target as the last.
+// Costs come from the terrain of every entered hex (Dijkstra). No step costs more than
+// `full_movement`, the unit's movement for a whole turn, so a unit that hasn't moved yet can
+// always enter a passable neighbour however slow the terrain is.
 pub fn movement_range(
     start: Hex,
     movement: i32,
+    full_movement: i32,
     battle: &BattleState,
 ) -> HashMap<Hex, (i32, Vec<Hex>)> {
-    if movement <= 0 {
-        let mut map = HashMap::new();
-        map.insert(start, (0, vec![start]));
-        return map;
-    }
-
-    let mut visited: HashMap<Hex, (i32, Vec<Hex>)> = HashMap::new();
-    let mut frontier: VecDeque<(Hex, i32, Vec<Hex>)> = VecDeque::new();
-
-    frontier.push_back((start, 0, vec![start]));
+    let mut best: HashMap<Hex, (i32, Hex)> = HashMap::new();
+    let mut frontier: BinaryHeap<Reverse<(i32, Hex)>> = BinaryHeap::new();
 
-    while let Some((hex, dist, path)) = frontier.pop_front() {
-        if visited.contains_key(&hex) || (!battle.is_hex_passable(hex) && hex != start) {
-            continue;
-        }
-
-        visited.insert(hex, (dist, path.clone()));
+    best.insert(start, (0, start));
+    frontier.push(Reverse((0, start)));
 
-        if dist >= movement {
-            continue;
+    while let Some(Reverse((cost, hex))) = frontier.pop() {
+        if cost > best[&hex].0 {
+            continue; // stale entry
         }
 
         for neighbor in hex_neighbors(hex, battle.grid_width, battle.grid_height) {
-            if !visited.contains_key(&neighbor) {
-                let mut new_path = path.clone();
-                new_path.push(neighbor);
-                frontier.push_back((neighbor, dist + 1, new_path));
+            let step = match battle.movement_cost(neighbor) {
+                Some(step) => step.min(full_movement.max(1)),
+                None => continue,
+            };
+            let new_cost = cost + step;
+            if new_cost > movement || best.get(&neighbor).is_some_and(|&(c, _)| c <= new_cost) {
+                continue;
             }
+            best.insert(neighbor, (new_cost, hex));
+            frontier.push(Reverse((new_cost, neighbor)));
         }
     }
 
-    visited
+    best.iter()
+        .map(|(&hex, &(cost, _))| (hex, (cost, reconstruct_path(start, hex, &best))))
+        .collect()
 }
 
 pub fn hex_neighbors(hex: Hex, grid_width: i32, grid_height: i32) -> Vec<Hex> {
@@ -64,44 +65,74 @@ pub fn hex_neighbors(hex: Hex, grid_width: i32, grid_height: i32) -> Vec<Hex> {
         .collect()
 }
 
-pub fn bfs_path(start: Hex, goal: Hex, battle: &BattleState) -> Vec<Hex> {
-    use std::collections::{HashMap, VecDeque};
+fn reconstruct_path(start: Hex, goal: Hex, came_from: &HashMap<Hex, (i32, Hex)>) -> Vec<Hex> {
+    let mut path = vec![goal];
+    let mut cur = goal;
+    while cur != start {
+        cur = came_from[&cur].1;
+        path.push(cur);
+    }
+    path.reverse();
+    path
+}
 
-    if start == goal {
-        return vec![start];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battlefield::{HexInfo, Terrain};
+    use crate::hex;
+    use crate::testing::*;
+
+    fn open_field(terrain: &[(Hex, Terrain)]) -> BattleState {
+        let mut battle =
+            BattleState::new(&[hero(0, stats(10, (1, 1), 1, 2))], &[], &test_map(), 1).unwrap();
+        for info in battle.hex_map.values_mut() {
+            *info = HexInfo::new(Terrain::Plain);
+        }
+        for &(hex, t) in terrain {
+            battle.hex_map.insert(hex, HexInfo::new(t));
+        }
+        battle.update_occupied_hexes();
+        battle
     }
 
-    let mut frontier: VecDeque<Hex> = VecDeque::new();
-    let mut came_from: HashMap<Hex, Hex> = HashMap::new();
-    let mut visited: HashSet<Hex> = HashSet::new();
+    #[test]
+    fn forest_costs_two_movement() {
+        let start = hex!(5, 5);
+        let forest: Vec<_> = hex_neighbors(start, 10, 10)
+            .into_iter()
+            .map(|h| (h, Terrain::Forest))
+            .collect();
+        let battle = open_field(&forest);
 
-    frontier.push_back(start);
-    visited.insert(start);
+        assert_eq!(movement_range(start, 1, 2, &battle).len(), 1);
 
-    while let Some(current) = frontier.pop_front() {
-        for neighbor in hex_neighbors(current, battle.grid_width, battle.grid_height) {
-            if visited.contains(&neighbor)
-                || (!battle.is_hex_passable(neighbor) && neighbor != goal)
-            {
-                continue;
-            }
+        let range = movement_range(start, 2, 2, &battle);
+        assert_eq!(range.len(), 7);
+        assert!(forest.iter().all(|(h, _)| range[h].0 == 2));
+    }
 
-            came_from.insert(neighbor, current);
-            visited.insert(neighbor);
-            frontier.push_back(neighbor);
-
-            if neighbor == goal {
-                let mut path = Vec::new();
-                let mut cur = goal;
-                path.push(cur);
-                while cur != start {
-                    cur = came_from[&cur];
-                    path.push(cur);
-                }
-                path.reverse();
-                return path;
-            }
-        }
+    #[test]
+    fn water_is_slow_and_mountains_block() {
+        let start = hex!(5, 5);
+        let battle = open_field(&[
+            (hex!(5, 4), Terrain::Water),
+            (hex!(5, 6), Terrain::Mountain),
+        ]);
+
+        let range = movement_range(start, 3, 3, &battle);
+
+        assert_eq!(range[&hex!(5, 4)].0, 3);
+        assert_eq!(range[&hex!(5, 4)].1, vec![start, hex!(5, 4)]);
+        assert!(!range.contains_key(&hex!(5, 6)));
+    }
+
+    #[test]
+    fn slow_units_can_still_wade_in_with_a_full_turn() {
+        let start = hex!(5, 5);
+        let battle = open_field(&[(hex!(5, 4), Terrain::Water)]);
+
+        assert_eq!(movement_range(start, 2, 2, &battle)[&hex!(5, 4)].0, 2);
+        assert!(!movement_range(start, 1, 2, &battle).contains_key(&hex!(5, 4)));
     }
-    vec![]
 }
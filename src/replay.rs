@@ -0,0 +1,211 @@
+use crate::ai::{AiDecision, Difficulty};
+use crate::battlemap::BattleMap;
+use crate::battlestate::{BattleCommand, BattleState};
+use crate::character::{Enemy, Hero};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const LAST_REPLAY_PATH: &str = "replays/last_battle.json";
+
+/// Safety net against a corrupted log spinning the battle forever.
+const MAX_TICKS_PER_STEP: usize = 1000;
+
+/// Everything needed to rebuild a battle from scratch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BattleSetup {
+    pub heroes: Vec<Hero>,
+    pub enemies: Vec<Enemy>,
+    pub map: BattleMap,
+    pub seed: u64,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LogEntry {
+    Command(BattleCommand),
+    Ai(AiDecision),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BattleLog {
+    pub setup: BattleSetup,
+    pub entries: Vec<LogEntry>,
+}
+
+impl BattleLog {
+    pub fn new(setup: BattleSetup) -> Self {
+        Self {
+            setup,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("writing replay {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("reading replay {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("parsing replay {}", path.display()))
+    }
+}
+
+/// Plays a saved log back on a fresh `BattleState`, one entry at a time.
+pub struct BattleReplay {
+    pub battle: BattleState,
+    log: BattleLog,
+    cursor: usize,
+    pub paused: bool,
+    /// Set when the replayed battle stops matching the log, e.g. after a rules change.
+    pub diverged: bool,
+    /// Time of the last step, used by the UI to pace playback.
+    pub last_step_at: f64,
+}
+
+impl BattleReplay {
+    pub fn new(log: BattleLog) -> anyhow::Result<Self> {
+        log.setup.map.validate()?;
+        Ok(Self {
+            battle: BattleState::from_setup(&log.setup)?,
+            log,
+            cursor: 0,
+            paused: true,
+            diverged: false,
+            last_step_at: 0.0,
+        })
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.cursor, self.log.entries.len())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.battle.result.is_some() || (self.diverged && self.cursor >= self.log.entries.len())
+    }
+
+    /// Advances the battle up to and including the next log entry. Once the log is exhausted
+    /// it ticks the battle until it is decided.
+    pub fn step(&mut self) {
+        let Some(entry) = self.log.entries.get(self.cursor).cloned() else {
+            self.tick_until(|battle| battle.result.is_some());
+            if self.battle.result.is_none() {
+                self.diverged = true;
+            }
+            return;
+        };
+
+        match &entry {
+            LogEntry::Command(cmd) => {
+                self.tick_until(|battle| battle.awaiting_input());
+                self.battle.handle_command(cmd.clone());
+            }
+            LogEntry::Ai(_) => {
+                let cursor = self.cursor;
+                self.tick_until(|battle| battle.log.entries.len() > cursor);
+            }
+        }
+
+        if self.battle.log.entries.get(self.cursor) != Some(&entry) {
+            println!("Replay diverged at entry {}", self.cursor);
+            self.diverged = true;
+        }
+        self.cursor += 1;
+    }
+
+    fn tick_until(&mut self, done: impl Fn(&BattleState) -> bool) {
+        for _ in 0..MAX_TICKS_PER_STEP {
+            if done(&self.battle) || self.battle.result.is_some() {
+                return;
+            }
+            self.battle.tick();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battlestate::UnitRef;
+    use crate::testing::*;
+
+    fn recorded_battle() -> BattleState {
+        let heroes = [hero(0, stats(120, (8, 16), 12, 2))];
+        let enemies = [
+            enemy(0, "Goblin", stats(30, (5, 10), 15, 3)),
+            enemy(1, "Orc", stats(60, (6, 12), 5, 2)),
+        ];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 2024).unwrap();
+        run_battle(&mut battle, 1000, charge_closest_enemy);
+        battle
+    }
+
+    fn health(battle: &BattleState) -> Vec<(UnitRef, i32)> {
+        let mut units = battle.turn_order.clone();
+        units.sort();
+        units
+            .into_iter()
+            .map(|u| (u, battle.unit_current_health(u)))
+            .collect()
+    }
+
+    #[test]
+    fn log_records_commands_and_ai_decisions() {
+        let battle = recorded_battle();
+        let entries = &battle.log.entries;
+
+        assert!(entries.iter().any(|e| matches!(e, LogEntry::Ai(_))));
+        assert!(entries
+            .iter()
+            .any(|e| matches!(e, LogEntry::Command(BattleCommand::RequestEndTurn))));
+    }
+
+    #[test]
+    fn saved_log_replays_to_the_same_outcome() {
+        let original = recorded_battle();
+        let json = serde_json::to_string(&original.log).unwrap();
+        let log: BattleLog = serde_json::from_str(&json).unwrap();
+
+        let mut replay = BattleReplay::new(log).unwrap();
+        while !replay.is_finished() {
+            replay.step();
+        }
+
+        assert!(!replay.diverged);
+        assert_eq!(
+            format!("{:?}", replay.battle.result),
+            format!("{:?}", original.result)
+        );
+        assert_eq!(health(&replay.battle), health(&original));
+        assert_eq!(replay.battle.log.entries, original.log.entries);
+    }
+
+    #[test]
+    fn tampered_log_is_reported_as_diverged() {
+        let original = recorded_battle();
+        let mut log = original.log.clone();
+        let ai_idx = log
+            .entries
+            .iter()
+            .position(|e| matches!(e, LogEntry::Ai(_)))
+            .unwrap();
+        if let LogEntry::Ai(decision) = &mut log.entries[ai_idx] {
+            decision.move_to.q += 100;
+        }
+
+        let mut replay = BattleReplay::new(log).unwrap();
+        for _ in 0..=ai_idx {
+            replay.step();
+        }
+
+        assert!(replay.diverged);
+    }
+}
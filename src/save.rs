@@ -0,0 +1,340 @@
+use crate::ai::Difficulty;
+use crate::battlestate::BattleState;
+use crate::character::Hero;
+use crate::crafting::MaterialStash;
+use crate::gamestate::{GameState, Progress, Screen};
+use crate::inventory::Storage;
+use crate::loot::BattleRewards;
+use anyhow::{anyhow, bail, Context};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+pub const SAVES_DIR: &str = "saves";
+pub const SAVE_SLOTS: u32 = 3;
+
+/// Bump when `SaveData` changes shape and add a step to `MIGRATIONS`.
+pub const SAVE_VERSION: u32 = 5;
+
+/// Upgrades a save from version `i + 1` to `i + 2`. Steps work on raw JSON so they don't depend
+/// on the current shape of the game types.
+type Migration = fn(&mut Value) -> anyhow::Result<()>;
+const MIGRATIONS: [Migration; (SAVE_VERSION - 1) as usize] =
+    [add_battle, add_item_ids, add_adventure_map, add_difficulty];
+
+/// Version 2 saves the battle in progress.
+fn add_battle(save: &mut Value) -> anyhow::Result<()> {
+    let save = save
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("save is not an object"))?;
+    save.insert("battle".to_string(), Value::Null);
+    save.insert("current_encounter".to_string(), Value::Null);
+    Ok(())
+}
+
+/// Version 3 keeps a campaign-wide item id counter and the last battle rewards. Battles gained
+/// items and loot at the same time, so a battle in progress can't be resumed and is dropped.
+fn add_item_ids(save: &mut Value) -> anyhow::Result<()> {
+    let mut highest = 0;
+    let ids = |items: Option<&Value>| -> Vec<u64> {
+        let items: Vec<&Value> = match items {
+            Some(Value::Array(items)) => items.iter().collect(),
+            Some(Value::Object(items)) => items.values().collect(),
+            _ => Vec::new(),
+        };
+        items
+            .iter()
+            .filter_map(|item| item.get("id").and_then(Value::as_u64))
+            .collect()
+    };
+    for hero in save["party"].as_array().into_iter().flatten() {
+        let inventory = &hero["inventory"];
+        for id in ids(inventory.get("backpack"))
+            .into_iter()
+            .chain(ids(inventory.get("equipped")))
+        {
+            highest = highest.max(id);
+        }
+    }
+    for id in ids(save["storage"].get("items")) {
+        highest = highest.max(id);
+    }
+
+    let save = save
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("save is not an object"))?;
+    save.insert("next_item_id".to_string(), (highest + 1).into());
+    save.insert("rewards".to_string(), Value::Null);
+    if save.get("battle").is_some_and(|battle| !battle.is_null()) {
+        eprintln!("The battle in progress was saved by an older version and can't be resumed");
+        save.insert("battle".to_string(), Value::Null);
+        save.insert("current_encounter".to_string(), Value::Null);
+        if save.get("screen").and_then(Value::as_str) == Some("Battle") {
+            save.insert("screen".to_string(), "Menu".into());
+        }
+    }
+    Ok(())
+}
+
+/// Version 4 keeps the adventure map in the progress. Older saves get a new map on load.
+fn add_adventure_map(save: &mut Value) -> anyhow::Result<()> {
+    let progress = save
+        .get_mut("progress")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| anyhow!("save has no progress"))?;
+    progress.insert("map".to_string(), Value::Null);
+    Ok(())
+}
+
+/// Version 5 keeps the difficulty setting.
+fn add_difficulty(save: &mut Value) -> anyhow::Result<()> {
+    let save = save
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("save is not an object"))?;
+    save.insert("difficulty".to_string(), "Normal".into());
+    Ok(())
+}
+
+/// Everything about a campaign that outlives a play session. Assets and content are loaded from
+/// disk on start and are not part of a save.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SaveData {
+    pub version: u32,
+    pub party: Vec<Hero>,
+    pub storage: Storage,
+    pub materials: MaterialStash,
+    pub screen: Screen,
+    pub progress: Progress,
+    pub battle: Option<BattleState>,
+    pub current_encounter: Option<String>,
+    pub rewards: Option<BattleRewards>,
+    pub next_item_id: u64,
+    pub difficulty: Difficulty,
+}
+
+pub fn slot_path(slot: u32) -> PathBuf {
+    Path::new(SAVES_DIR).join(format!("slot{}.json", slot))
+}
+
+impl SaveData {
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("writing save {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("reading save {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("loading save {}", path.display()))
+    }
+
+    /// Parses a save of any supported version, migrating it to the current one.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let mut value: Value = serde_json::from_str(json)?;
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("save has no version number"))? as u32;
+
+        if version == 0 {
+            bail!("save version 0 is not supported");
+        }
+        if version > SAVE_VERSION {
+            bail!(
+                "save version {} is newer than this game supports ({})",
+                version,
+                SAVE_VERSION
+            );
+        }
+
+        for (from, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+            migrate(&mut value)
+                .with_context(|| format!("migrating save from version {}", from + 1))?;
+            value["version"] = (from as u32 + 2).into();
+        }
+
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl GameState {
+    pub fn to_save(&self) -> SaveData {
+        SaveData {
+            version: SAVE_VERSION,
+            party: self.player_party.clone(),
+            storage: self.storage.clone(),
+            materials: self.materials.clone(),
+            // Replays are not saved, so they resume from the menu.
+            screen: match self.current_screen {
+                Screen::Replay => Screen::Menu,
+                screen => screen,
+            },
+            progress: self.progress.clone(),
+            battle: self.battle.clone(),
+            current_encounter: self.current_encounter.clone(),
+            rewards: self.rewards.clone(),
+            next_item_id: self.next_item_id,
+            difficulty: self.difficulty,
+        }
+    }
+
+    /// Replaces the campaign with a save. Assets and content stay as they are.
+    pub fn apply_save(&mut self, save: SaveData) {
+        self.player_party = save.party;
+        self.storage = save.storage;
+        self.materials = save.materials;
+        self.current_screen = save.screen;
+        self.progress = save.progress;
+        self.battle = save.battle;
+        self.replay = None;
+        self.current_encounter = save.current_encounter;
+        self.rewards = save.rewards;
+        self.next_item_id = save.next_item_id;
+        self.difficulty = save.difficulty;
+        if self.progress.map.is_none() {
+            self.new_map(thread_rng().gen());
+        }
+    }
+
+    pub fn save_slot(&self, slot: u32) -> anyhow::Result<()> {
+        self.to_save().save(slot_path(slot))
+    }
+
+    pub fn load_slot(&mut self, slot: u32) -> anyhow::Result<()> {
+        let save = SaveData::load(slot_path(slot))?;
+        self.apply_save(save);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn campaign() -> GameState {
+        let mut state = GameState::new();
+        state.player_party = vec![hero(0, stats(50, (8, 12), 10, 2))];
+        state.player_party[0].xp = 130;
+        state.materials.add("iron_ore", 4);
+        state
+            .progress
+            .completed_encounters
+            .push("goblin_ambush".to_string());
+        state.current_screen = Screen::Victory;
+        state
+    }
+
+    #[test]
+    fn save_round_trips_the_campaign() {
+        let json = serde_json::to_string(&campaign().to_save()).unwrap();
+
+        let mut state = GameState::new();
+        state.apply_save(SaveData::from_json(&json).unwrap());
+
+        assert_eq!(state.player_party[0].xp, 130);
+        assert_eq!(state.materials.count("iron_ore"), 4);
+        assert_eq!(state.progress.completed_encounters, ["goblin_ambush"]);
+        assert!(state.current_screen == Screen::Victory);
+    }
+
+    #[test]
+    fn battle_in_progress_is_saved() {
+        let mut state = campaign();
+        state.player_party = vec![hero(0, stats(100, (8, 16), 12, 2))];
+        state.battle = Some(
+            BattleState::new(
+                &state.player_party,
+                &[enemy(0, "Goblin", stats(30, (5, 10), 15, 3))],
+                &test_map(),
+                5,
+            )
+            .unwrap(),
+        );
+        state.current_screen = Screen::Battle;
+        let json = serde_json::to_string(&state.to_save()).unwrap();
+
+        let mut loaded = GameState::new();
+        loaded.apply_save(SaveData::from_json(&json).unwrap());
+
+        assert!(loaded.current_screen == Screen::Battle);
+        assert_eq!(loaded.battle.unwrap().seed, 5);
+    }
+
+    #[test]
+    fn version_1_saves_are_migrated() {
+        let mut save = serde_json::to_value(campaign().to_save()).unwrap();
+        let fields = save.as_object_mut().unwrap();
+        fields.remove("battle");
+        fields.remove("current_encounter");
+        fields["progress"].as_object_mut().unwrap().remove("map");
+        fields.remove("difficulty");
+        fields.insert("version".to_string(), 1.into());
+
+        let save = SaveData::from_json(&save.to_string()).unwrap();
+
+        assert_eq!(save.version, SAVE_VERSION);
+        assert!(save.battle.is_none());
+        assert_eq!(save.next_item_id, 1);
+        assert_eq!(save.party[0].xp, 130);
+        assert!(save.progress.map.is_none());
+        assert_eq!(save.difficulty, Difficulty::Normal);
+    }
+
+    #[test]
+    fn version_2_battles_in_progress_are_dropped() {
+        let mut state = campaign();
+        state.battle = Some(
+            BattleState::new(
+                &state.player_party,
+                &[enemy(0, "Goblin", stats(30, (5, 10), 15, 3))],
+                &test_map(),
+                5,
+            )
+            .unwrap(),
+        );
+        state.current_encounter = Some("goblin_ambush".to_string());
+        state.current_screen = Screen::Battle;
+        let mut save = serde_json::to_value(state.to_save()).unwrap();
+        let fields = save.as_object_mut().unwrap();
+        for field in ["next_item_id", "rewards", "difficulty"] {
+            fields.remove(field);
+        }
+        fields["progress"].as_object_mut().unwrap().remove("map");
+        // Battles of this version had no item or loot state.
+        let battle = fields["battle"].as_object_mut().unwrap();
+        for field in ["selected_item", "consumed_items", "loot"] {
+            battle.remove(field);
+        }
+        for hero in battle["heroes"].as_object_mut().unwrap().values_mut() {
+            hero.as_object_mut().unwrap().remove("items");
+        }
+        fields.insert("version".to_string(), 2.into());
+
+        let save = SaveData::from_json(&save.to_string()).unwrap();
+
+        assert!(save.battle.is_none());
+        assert!(save.current_encounter.is_none());
+        assert!(save.screen == Screen::Menu);
+        assert_eq!(save.party[0].xp, 130);
+    }
+
+    #[test]
+    fn unsupported_versions_are_readable_errors() {
+        let mut save = serde_json::to_value(campaign().to_save()).unwrap();
+
+        save["version"] = (SAVE_VERSION + 1).into();
+        let err = SaveData::from_json(&save.to_string()).unwrap_err();
+        assert!(format!("{:#}", err).contains("newer"));
+
+        save.as_object_mut().unwrap().remove("version");
+        let err = SaveData::from_json(&save.to_string()).unwrap_err();
+        assert!(format!("{:#}", err).contains("no version"));
+    }
+}
@@ -0,0 +1,531 @@
+//! Headless battles with both sides played by the AI, for checking balance changes:
+//!
+//! `cargo run -- simulate --battles 200 --encounter goblin_ambush --format csv --out sim.csv`
+
+use crate::ai::{hero_ai, AiBehavior, Difficulty};
+use crate::battlemap::BattleMap;
+use crate::battlestate::{BattleCommand, BattleResult, BattleState, TurnPhase, UnitRef};
+use crate::content::ContentRegistry;
+use crate::replay::LogEntry;
+use anyhow::{anyhow, bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+const USAGE: &str =
+    "usage: simulate [--battles N] [--seed N] [--encounter ID] [--heroes BEHAVIOR] \
+[--difficulty Easy|Normal|Hard] [--max-turns N] [--format json|csv] [--out PATH]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+/// What to simulate. Battle `i` is seeded with `seed + i`, so a run can be repeated exactly.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    pub battles: usize,
+    pub seed: u64,
+    /// Encounter id, the first campaign battle if not given.
+    pub encounter: Option<String>,
+    /// Behaviour the heroes fight with, on top of the default weights.
+    pub heroes: AiBehavior,
+    pub difficulty: Difficulty,
+    /// Battles still going after this many rounds count as undecided.
+    pub max_turns: u32,
+    pub format: ReportFormat,
+    /// Where the report goes, stdout if not given.
+    pub out: Option<PathBuf>,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            battles: 100,
+            seed: 0,
+            encounter: None,
+            heroes: AiBehavior::Standard,
+            difficulty: Difficulty::Normal,
+            max_turns: 100,
+            format: ReportFormat::Json,
+            out: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SimReport {
+    pub encounter: String,
+    pub seed: u64,
+    pub battles: usize,
+    pub heroes: AiBehavior,
+    pub difficulty: Difficulty,
+    pub wins: usize,
+    pub undecided: usize,
+    pub win_rate: f32,
+    /// Rounds per battle, a round being every unit taking one turn.
+    pub average_turns: f32,
+    /// Damage over time from effects per battle, which isn't credited to any unit.
+    pub average_effect_damage: f32,
+    pub units: Vec<UnitReport>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct UnitReport {
+    pub side: String,
+    pub id: u32,
+    pub name: String,
+    /// Damage dealt per battle. Overkill doesn't count.
+    pub average_damage: f32,
+    /// Times each ability was used over all battles.
+    pub ability_uses: BTreeMap<String, usize>,
+}
+
+/// Totals of a single battle.
+#[derive(Debug, Default)]
+struct BattleStats {
+    result: Option<BattleResult>,
+    turns: u32,
+    damage: BTreeMap<UnitRef, i32>,
+    effect_damage: i32,
+    ability_uses: BTreeMap<UnitRef, BTreeMap<String, usize>>,
+}
+
+/// Runs the `simulate` subcommand with the arguments after it.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let simulation = Simulation::parse(args)?;
+    let content = ContentRegistry::load()?;
+    let report = simulation.run(&content)?;
+    let text = match simulation.format {
+        ReportFormat::Json => serde_json::to_string_pretty(&report)?,
+        ReportFormat::Csv => report.to_csv(),
+    };
+    match &simulation.out {
+        Some(path) => {
+            std::fs::write(path, text).with_context(|| format!("writing report {}", path.display()))
+        }
+        None => {
+            println!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+impl Simulation {
+    pub fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut simulation = Self::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", flag, USAGE))?;
+            match flag.as_str() {
+                "--battles" => simulation.battles = value.parse()?,
+                "--seed" => simulation.seed = value.parse()?,
+                "--encounter" => simulation.encounter = Some(value.clone()),
+                "--heroes" => simulation.heroes = parse_variant(value)?,
+                "--difficulty" => simulation.difficulty = parse_variant(value)?,
+                "--max-turns" => simulation.max_turns = value.parse()?,
+                "--format" => {
+                    simulation.format = match value.as_str() {
+                        "json" => ReportFormat::Json,
+                        "csv" => ReportFormat::Csv,
+                        _ => bail!("unknown format {}\n{}", value, USAGE),
+                    }
+                }
+                "--out" => simulation.out = Some(PathBuf::from(value)),
+                _ => bail!("unknown option {}\n{}", flag, USAGE),
+            }
+        }
+        Ok(simulation)
+    }
+
+    pub fn run(&self, content: &ContentRegistry) -> anyhow::Result<SimReport> {
+        let encounter_id = match &self.encounter {
+            Some(id) => id.clone(),
+            None => content
+                .campaign
+                .battles
+                .first()
+                .cloned()
+                .ok_or_else(|| anyhow!("the campaign has no battles to simulate"))?,
+        };
+        let encounter = content.encounter(&encounter_id)?;
+        let map = BattleMap::load(&encounter.map)?;
+        let heroes = content.starting_party()?;
+        let mut enemies = content.encounter_enemies(encounter)?;
+        for enemy in &mut enemies {
+            self.difficulty.scale_stats(&mut enemy.stats);
+        }
+
+        let mut all = Vec::new();
+        for i in 0..self.battles {
+            let mut battle = BattleState::new(&heroes, &enemies, &map, self.seed + i as u64)?
+                .with_difficulty(self.difficulty);
+            let names: Vec<(UnitRef, String)> = battle
+                .turn_order
+                .iter()
+                .map(|&u| (u, battle.unit_name(u).unwrap_or_default()))
+                .collect();
+            all.push((names, play(&mut battle, self.heroes, self.max_turns)));
+        }
+        Ok(self.report(encounter_id, &all))
+    }
+
+    fn report(
+        &self,
+        encounter: String,
+        all: &[(Vec<(UnitRef, String)>, BattleStats)],
+    ) -> SimReport {
+        let battles = all.len().max(1) as f32;
+        let wins = all
+            .iter()
+            .filter(|(_, s)| matches!(s.result, Some(BattleResult::Victory)))
+            .count();
+        let undecided = all.iter().filter(|(_, s)| s.result.is_none()).count();
+
+        let mut units: BTreeMap<UnitRef, UnitReport> = BTreeMap::new();
+        for (names, stats) in all {
+            for (unit, name) in names {
+                let report = units.entry(*unit).or_insert_with(|| UnitReport {
+                    side: match unit {
+                        UnitRef::Hero(_) => "hero".to_string(),
+                        UnitRef::Enemy(_) => "enemy".to_string(),
+                    },
+                    id: match *unit {
+                        UnitRef::Hero(id) | UnitRef::Enemy(id) => id,
+                    },
+                    name: name.clone(),
+                    ..Default::default()
+                });
+                report.average_damage += stats.damage.get(unit).copied().unwrap_or(0) as f32;
+                for (ability, uses) in stats.ability_uses.get(unit).into_iter().flatten() {
+                    *report.ability_uses.entry(ability.clone()).or_insert(0) += uses;
+                }
+            }
+        }
+        for report in units.values_mut() {
+            report.average_damage /= battles;
+        }
+
+        SimReport {
+            encounter,
+            seed: self.seed,
+            battles: all.len(),
+            heroes: self.heroes,
+            difficulty: self.difficulty,
+            wins,
+            undecided,
+            win_rate: wins as f32 / battles,
+            average_turns: all.iter().map(|(_, s)| s.turns).sum::<u32>() as f32 / battles,
+            average_effect_damage: all.iter().map(|(_, s)| s.effect_damage).sum::<i32>() as f32
+                / battles,
+            units: units.into_values().collect(),
+        }
+    }
+}
+
+impl SimReport {
+    /// One row per unit and ability, with the battle-wide numbers repeated on every row so the
+    /// file can be filtered and pivoted on its own.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "encounter,seed,battles,heroes,difficulty,wins,undecided,win_rate,average_turns,average_effect_damage,side,id,name,average_damage,ability,uses\n",
+        );
+        let heroes = serde_json::to_string(&self.heroes).unwrap_or_default();
+        let summary = format!(
+            "{},{},{},{},{:?},{},{},{:.3},{:.2},{:.2}",
+            self.encounter,
+            self.seed,
+            self.battles,
+            csv_field(heroes.trim_matches('"')),
+            self.difficulty,
+            self.wins,
+            self.undecided,
+            self.win_rate,
+            self.average_turns,
+            self.average_effect_damage
+        );
+        for unit in &self.units {
+            let unit_fields = format!(
+                "{},{},{},{:.2}",
+                unit.side,
+                unit.id,
+                csv_field(&unit.name),
+                unit.average_damage
+            );
+            if unit.ability_uses.is_empty() {
+                let _ = writeln!(csv, "{},{},,0", summary, unit_fields);
+            }
+            for (ability, uses) in &unit.ability_uses {
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{}",
+                    summary,
+                    unit_fields,
+                    csv_field(ability),
+                    uses
+                );
+            }
+        }
+        csv
+    }
+}
+
+/// Plays a battle to the end with the heroes on `behavior`, crediting damage to whoever's turn
+/// it was when it happened.
+fn play(battle: &mut BattleState, behavior: AiBehavior, max_turns: u32) -> BattleStats {
+    let mut stats = BattleStats {
+        turns: 1,
+        ..Default::default()
+    };
+    let mut acted: HashSet<UnitRef> = HashSet::new();
+    let mut logged = battle.log.entries.len();
+
+    while battle.result.is_none() {
+        if battle.phase == TurnPhase::Start {
+            let unit = battle.active_unit();
+            if !acted.insert(unit) {
+                stats.turns += 1;
+                if stats.turns > max_turns {
+                    stats.turns = max_turns;
+                    break;
+                }
+                acted.clear();
+                acted.insert(unit);
+            }
+        }
+
+        let phase = battle.phase;
+        let actor = battle.active_unit();
+        let before = health(battle);
+        if battle.awaiting_input() {
+            hero_turn(battle, actor, behavior, &mut stats);
+        } else {
+            battle.tick();
+        }
+        let dealt: i32 = before
+            .iter()
+            .map(|&(unit, hp)| hp - battle.unit_current_health(unit))
+            .filter(|&lost| lost > 0)
+            .sum();
+        if phase == TurnPhase::Start {
+            stats.effect_damage += dealt;
+        } else {
+            *stats.damage.entry(actor).or_insert(0) += dealt;
+        }
+
+        for entry in &battle.log.entries[logged..] {
+            if let LogEntry::Ai(decision) = entry {
+                if let Some(idx) = decision.ability {
+                    count_ability(battle, &mut stats, decision.unit, idx);
+                }
+            }
+        }
+        logged = battle.log.entries.len();
+    }
+
+    stats.result = battle.result;
+    stats
+}
+
+/// Turns the hero's planned move and attack into the commands a player would give.
+fn hero_turn(
+    battle: &mut BattleState,
+    hero: UnitRef,
+    behavior: AiBehavior,
+    stats: &mut BattleStats,
+) {
+    let decision = hero_ai(battle, hero, behavior);
+    if battle.unit_hex(hero) != Some(decision.move_to) {
+        battle.handle_command(BattleCommand::MoveSelectedUnit(decision.move_to));
+    }
+    if let (Some(idx), Some(target)) = (decision.ability, decision.target) {
+        if let Some(target_hex) = battle.unit_hex(target) {
+            let ready = battle.unit_action_available(hero) == Some(true);
+            battle.handle_command(BattleCommand::SelectAbility(idx));
+            battle.handle_command(BattleCommand::UseAbility(target_hex));
+            if ready && battle.unit_action_available(hero) == Some(false) {
+                count_ability(battle, stats, hero, idx);
+            }
+        }
+    }
+    battle.handle_command(BattleCommand::RequestEndTurn);
+}
+
+fn count_ability(battle: &BattleState, stats: &mut BattleStats, unit: UnitRef, idx: usize) {
+    let name = battle
+        .unit_abilities(unit)
+        .and_then(|abilities| abilities.get(idx))
+        .map_or_else(|| format!("#{}", idx), |a| a.name.clone());
+    *stats
+        .ability_uses
+        .entry(unit)
+        .or_default()
+        .entry(name)
+        .or_insert(0) += 1;
+}
+
+fn health(battle: &BattleState) -> Vec<(UnitRef, i32)> {
+    battle
+        .turn_order
+        .iter()
+        .map(|&u| (u, battle.unit_current_health(u)))
+        .collect()
+}
+
+/// Enum variants can be given bare (`Berserker`) or as JSON (`{"Guardian":{"radius":2}}`).
+fn parse_variant<T: DeserializeOwned>(value: &str) -> anyhow::Result<T> {
+    serde_json::from_str(value)
+        .or_else(|_| serde_json::from_str(&format!("\"{}\"", value)))
+        .with_context(|| format!("can't parse {}", value))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_report() {
+        let content = ContentRegistry::load().unwrap();
+        let simulation = Simulation {
+            battles: 3,
+            seed: 11,
+            encounter: Some("goblin_warband".to_string()),
+            max_turns: 20,
+            ..Default::default()
+        };
+
+        let a = simulation.run(&content).unwrap();
+        let b = simulation.run(&content).unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(a.battles, 3);
+        assert_eq!(a.units.len(), 1 + 5);
+    }
+
+    #[test]
+    fn shipped_encounters_are_decided() {
+        let content = ContentRegistry::load().unwrap();
+        let mut ids: Vec<_> = content.encounters.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            for difficulty in [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard] {
+                let simulation = Simulation {
+                    battles: 10,
+                    encounter: Some(id.clone()),
+                    difficulty,
+                    ..Default::default()
+                };
+
+                let report = simulation.run(&content).unwrap();
+
+                assert_eq!(report.undecided, 0, "{} on {:?}", id, difficulty);
+            }
+        }
+    }
+
+    #[test]
+    fn credits_damage_and_abilities_to_the_unit_that_dealt_them() {
+        let heroes = [hero(0, stats(200, (20, 25), 12, 2))];
+        let enemies = [enemy(0, "Goblin", stats(30, (1, 2), 15, 3))];
+        let mut battle = BattleState::new(&heroes, &enemies, &test_map(), 7).unwrap();
+
+        let stats = play(&mut battle, AiBehavior::Berserker, 50);
+
+        assert!(matches!(stats.result, Some(BattleResult::Victory)));
+        assert_eq!(stats.damage.get(&UnitRef::Hero(0)), Some(&30));
+        let hero_uses = &stats.ability_uses[&UnitRef::Hero(0)];
+        assert!(hero_uses["Slash"] >= 2);
+        let dealt_to_hero = 200 - battle.unit_current_health(UnitRef::Hero(0));
+        assert_eq!(
+            stats.damage.get(&UnitRef::Enemy(0)).copied().unwrap_or(0),
+            dealt_to_hero
+        );
+    }
+
+    #[test]
+    fn parses_behaviours_by_name_or_as_json() {
+        let simulation = Simulation::parse(&args(&[
+            "--battles",
+            "5",
+            "--heroes",
+            r#"{"Guardian":{"radius":2}}"#,
+            "--difficulty",
+            "Hard",
+            "--format",
+            "csv",
+        ]))
+        .unwrap();
+
+        assert_eq!(simulation.battles, 5);
+        assert_eq!(simulation.heroes, AiBehavior::Guardian { radius: 2 });
+        assert_eq!(simulation.difficulty, Difficulty::Hard);
+        assert_eq!(simulation.format, ReportFormat::Csv);
+        assert!(Simulation::parse(&args(&["--heroes", "Reckless"])).is_err());
+        assert!(Simulation::parse(&args(&["--rounds", "3"])).is_err());
+    }
+
+    #[test]
+    fn csv_has_a_row_per_unit_and_ability() {
+        let mut uses = BTreeMap::new();
+        uses.insert("Slash".to_string(), 4);
+        uses.insert("Bola Throw".to_string(), 1);
+        let report = SimReport {
+            encounter: "ambush".to_string(),
+            seed: 3,
+            battles: 2,
+            heroes: AiBehavior::Standard,
+            difficulty: Difficulty::Normal,
+            wins: 1,
+            undecided: 0,
+            win_rate: 0.5,
+            average_turns: 6.0,
+            average_effect_damage: 2.5,
+            units: vec![
+                UnitReport {
+                    side: "hero".to_string(),
+                    id: 0,
+                    name: "Fighter".to_string(),
+                    average_damage: 40.0,
+                    ability_uses: uses,
+                },
+                UnitReport {
+                    side: "enemy".to_string(),
+                    id: 0,
+                    name: "Goblin, the Sneaky".to_string(),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let csv = report.to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "ambush,3,2,Standard,Normal,1,0,0.500,6.00,2.50,hero,0,Fighter,40.00,Bola Throw,1"
+        );
+        assert_eq!(
+            lines[3],
+            "ambush,3,2,Standard,Normal,1,0,0.500,6.00,2.50,enemy,0,\"Goblin, the Sneaky\",0.00,,0"
+        );
+    }
+}
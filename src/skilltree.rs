@@ -0,0 +1,144 @@
+use crate::character::Hero;
+use crate::item::Stat;
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// One node of a class skill tree, as stored in `data/content/skill_trees.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SkillNode {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub cost: u32,
+    /// Nodes that have to be unlocked first.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Ability id granted by the node.
+    #[serde(default)]
+    pub ability: Option<String>,
+    /// Permanent stat bonus granted by the node.
+    #[serde(default)]
+    pub stats: Vec<(Stat, i32)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct SkillTree {
+    pub nodes: Vec<SkillNode>,
+}
+
+impl SkillTree {
+    pub fn node(&self, id: &str) -> Option<&SkillNode> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    /// Checks node ids are unique and prerequisites exist and don't form a cycle.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut seen = HashSet::new();
+        for node in &self.nodes {
+            if !seen.insert(node.id.as_str()) {
+                bail!("node {} is listed twice", node.id);
+            }
+            if let Some(id) = node.requires.iter().find(|id| self.node(id).is_none()) {
+                bail!("node {} requires unknown node {}", node.id, id);
+            }
+        }
+
+        // Every node must be reachable by unlocking nodes whose prerequisites are already met.
+        let mut unlocked: HashSet<&str> = HashSet::new();
+        while unlocked.len() < self.nodes.len() {
+            let before = unlocked.len();
+            for node in &self.nodes {
+                if node
+                    .requires
+                    .iter()
+                    .all(|id| unlocked.contains(id.as_str()))
+                {
+                    unlocked.insert(&node.id);
+                }
+            }
+            if unlocked.len() == before {
+                bail!("prerequisites form a cycle");
+            }
+        }
+        Ok(())
+    }
+
+    /// Node the hero is allowed to unlock right now.
+    pub fn unlockable(&self, hero: &Hero, id: &str) -> anyhow::Result<&SkillNode> {
+        let Some(node) = self.node(id) else {
+            bail!("{} has no skill {}", hero.class, id);
+        };
+        if hero.unlocked_skills.contains(&node.id) {
+            bail!("{} is already unlocked", node.name);
+        }
+        if let Some(missing) = node
+            .requires
+            .iter()
+            .find(|id| !hero.unlocked_skills.contains(id))
+        {
+            bail!("{} requires {}", node.name, missing);
+        }
+        if hero.skill_points < node.cost {
+            bail!(
+                "{} costs {} skill points, {} has {}",
+                node.name,
+                node.cost,
+                hero.name,
+                hero.skill_points
+            );
+        }
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn node(id: &str, cost: u32, requires: &[&str]) -> SkillNode {
+        SkillNode {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: String::new(),
+            cost,
+            requires: requires.iter().map(|r| r.to_string()).collect(),
+            ability: None,
+            stats: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn unlock_checks_prerequisites_and_points() {
+        let tree = SkillTree {
+            nodes: vec![node("toughness", 1, &[]), node("cleave", 2, &["toughness"])],
+        };
+        let mut fighter = hero(0, stats(50, (8, 12), 10, 2));
+        fighter.skill_points = 1;
+
+        assert!(tree.unlockable(&fighter, "cleave").is_err());
+        assert!(tree.unlockable(&fighter, "toughness").is_ok());
+
+        fighter.unlocked_skills.push("toughness".to_string());
+        assert!(tree.unlockable(&fighter, "toughness").is_err());
+        assert!(tree.unlockable(&fighter, "cleave").is_err());
+
+        fighter.skill_points = 2;
+        assert!(tree.unlockable(&fighter, "cleave").is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_and_cyclic_prerequisites() {
+        let unknown = SkillTree {
+            nodes: vec![node("a", 1, &["b"])],
+        };
+        let cycle = SkillTree {
+            nodes: vec![node("a", 1, &["b"]), node("b", 1, &["a"])],
+        };
+
+        assert!(unknown.validate().is_err());
+        assert!(cycle.validate().is_err());
+    }
+}
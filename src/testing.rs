@@ -0,0 +1,142 @@
+use crate::ai::{hex_distance, AiBehavior, AiWeights};
+use crate::battlemap::BattleMap;
+use crate::battlestate::{BattleCommand, BattleResult, BattleState, TurnPhase};
+use crate::character::{Ability, Enemy, Hero, LineOfSight, Stats};
+use crate::hexgrid::Hex;
+use crate::inventory::Inventory;
+use crate::item::{Consumable, Item, ItemSlot, Stat};
+
+pub fn test_map() -> BattleMap {
+    BattleMap::load("clearing").unwrap()
+}
+
+pub fn stats(hp: i32, damage: (i32, i32), initiative: i32, movement: i32) -> Stats {
+    Stats {
+        max_hp: hp,
+        hp,
+        damage,
+        attack: 10,
+        defense: 10,
+        initiative,
+        movement,
+    }
+}
+
+pub fn slash() -> Ability {
+    Ability {
+        id: 0,
+        name: "Slash".to_string(),
+        description: String::new(),
+        damage_modifier: 1.0,
+        effect: None,
+        range: 1,
+        line_of_sight: LineOfSight::Ignore,
+    }
+}
+
+pub fn hero(id: u32, stats: Stats) -> Hero {
+    Hero {
+        id,
+        name: "Fighter".to_string(),
+        class: "fighter".to_string(),
+        hex: Hex { q: 0, r: 0 },
+        stats,
+        xp: 0,
+        growth: Vec::new(),
+        skill_points: 0,
+        unlocked_skills: Vec::new(),
+        abilities: vec![slash()],
+        inventory: Inventory::new(),
+        effects: Vec::new(),
+    }
+}
+
+pub fn enemy(id: u32, name: &str, stats: Stats) -> Enemy {
+    Enemy {
+        id,
+        name: name.to_string(),
+        hex: Hex { q: 0, r: 0 },
+        stats,
+        abilities: Vec::new(),
+        effects: Vec::new(),
+        xp_reward: 10,
+        loot: Vec::new(),
+        ai: AiWeights::default(),
+        behavior: AiBehavior::Standard,
+    }
+}
+
+pub fn item(id: u64, slot: ItemSlot, stat_changes: Vec<(Stat, i32)>) -> Item {
+    Item {
+        id,
+        name: format!("{:?} item", slot),
+        slot: Some(slot),
+        stat_changes,
+        effects: Vec::new(),
+        consumable: None,
+        salvage: Default::default(),
+    }
+}
+
+pub fn consumable(id: u64, name: &str, consumable: Consumable) -> Item {
+    Item {
+        id,
+        name: name.to_string(),
+        slot: None,
+        stat_changes: Vec::new(),
+        effects: Vec::new(),
+        consumable: Some(consumable),
+        salvage: Default::default(),
+    }
+}
+
+/// Ticks the battle until it is decided, letting `hero_turn` play every hero turn.
+pub fn run_battle(
+    battle: &mut BattleState,
+    max_ticks: usize,
+    mut hero_turn: impl FnMut(&mut BattleState),
+) -> Option<BattleResult> {
+    for _ in 0..max_ticks {
+        if battle.result.is_some() {
+            break;
+        }
+        battle.tick();
+        if battle.phase == TurnPhase::Action && battle.is_player_turn() {
+            hero_turn(battle);
+        }
+    }
+    battle.result
+}
+
+pub fn pass_turn(battle: &mut BattleState) {
+    battle.handle_command(BattleCommand::RequestEndTurn);
+}
+
+/// Walks towards the closest enemy and hits anything in range of the first ability.
+pub fn charge_closest_enemy(battle: &mut BattleState) {
+    let mut enemy_hexes: Vec<Hex> = battle.enemies.values().map(|e| e.hex).collect();
+    enemy_hexes.sort();
+    let closest = |hex: Hex| {
+        enemy_hexes
+            .iter()
+            .map(|&e| hex_distance(hex, e))
+            .min()
+            .unwrap_or(0)
+    };
+
+    let destination = battle
+        .selected_unit_range
+        .keys()
+        .copied()
+        .min_by_key(|&h| (closest(h), h.q, h.r));
+    if let Some(hex) = destination {
+        battle.handle_command(BattleCommand::MoveSelectedUnit(hex));
+    }
+
+    for target in enemy_hexes {
+        battle.handle_command(BattleCommand::SelectAbility(0));
+        battle.handle_command(BattleCommand::UseAbility(target));
+    }
+    battle.handle_command(BattleCommand::CancelAction);
+    battle.handle_command(BattleCommand::RequestEndTurn);
+}
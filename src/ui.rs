@@ -1,14 +1,23 @@
-use crate::battlefield::Terrain;
-use crate::battlestate::{
-    BattleCommand, BattleResult, BattleState, InputMode, UnitRef,
-};
+use crate::adventure::{AdventureMap, NodeKind};
+use crate::assets::Assets;
+use crate::battlefield::{Object, Terrain};
+use crate::battlestate::{BattleCommand, BattleState, InputMode, UnitRef, UnitRender};
 use crate::button::Button;
-use crate::gamestate::{GameState, Screen};
+use crate::effect::Effect;
+use crate::gamestate::{
+    GameState, Hero, ItemDestination, ItemLocation, PartyView, Screen, WorkshopView,
+};
 use crate::hexgrid::Hex;
+use crate::item::{Consumable, Item, ItemSlot};
+use crate::replay::{BattleReplay, LAST_REPLAY_PATH};
+use crate::save::SAVE_SLOTS;
 use macroquad::prelude::*;
 
 const HEX_RADIUS: f32 = 40.0;
 const UNIT_SCALE: f32 = 0.8;
+const REPLAY_STEP_SECONDS: f64 = 0.5;
+/// Slot F5 saves the battle in progress to.
+const QUICK_SAVE_SLOT: u32 = 1;
 
 pub async fn run(state: &mut GameState) {
     loop {
@@ -31,17 +40,39 @@ fn update(state: &mut GameState) {
                 battle.tick();
                 handle_input(battle);
 
-                if let Some(result) = battle.result {
-                    match result {
-                        BattleResult::Victory => state.current_screen = Screen::Victory,
-                        BattleResult::Defeat => state.current_screen = Screen::Defeat,
+                if is_key_pressed(KeyCode::F5) {
+                    match state.save_slot(QUICK_SAVE_SLOT) {
+                        Ok(()) => println!("Quick-saved to slot {}", QUICK_SAVE_SLOT),
+                        Err(e) => eprintln!("Could not save game: {:#}", e),
+                    }
+                    return;
+                }
+
+                if battle.result.is_some() {
+                    if let Err(e) = battle.log.save(LAST_REPLAY_PATH) {
+                        eprintln!("Could not save replay: {:#}", e);
                     }
-                    state.battle = None; // clear the battle state
+                    state.finish_battle();
                 }
             }
         }
         Screen::Victory => {}
         Screen::Defeat => {}
+        Screen::Party => {}
+        Screen::Workshop => {}
+        Screen::Map => {}
+        Screen::Replay => {
+            if let Some(replay) = &mut state.replay {
+                let now = get_time();
+                if !replay.paused
+                    && !replay.is_finished()
+                    && now - replay.last_step_at >= REPLAY_STEP_SECONDS
+                {
+                    replay.step();
+                    replay.last_step_at = now;
+                }
+            }
+        }
     }
 }
 
@@ -64,6 +95,10 @@ pub fn hud_handle_input(battle: &mut BattleState) -> bool {
             println!("UI end turn consumed click");
             return true;
         }
+
+        if items_handle_input(battle) {
+            return true;
+        }
     }
 
     false
@@ -101,6 +136,16 @@ fn abilities_handle_input(battle: &mut BattleState) -> bool {
     false
 }
 
+fn items_handle_input(battle: &mut BattleState) -> bool {
+    for (i, button) in item_buttons(battle).into_iter().enumerate() {
+        if button.clicked() {
+            battle.handle_command(BattleCommand::SelectItem(i));
+            return true;
+        }
+    }
+    false
+}
+
 pub fn battlefield_handle_input(battle: &mut BattleState) {
     if is_mouse_button_pressed(MouseButton::Left) {
         if let Some(hex) = screen_to_hex(
@@ -119,6 +164,7 @@ pub fn battlefield_handle_input(battle: &mut BattleState) {
                 InputMode::AbilityTarget { .. } => {
                     battle.handle_command(BattleCommand::UseAbility(hex))
                 }
+                InputMode::ItemTarget(_) => battle.handle_command(BattleCommand::UseItem(hex)),
             }
         }
     }
@@ -134,12 +180,16 @@ async fn draw(state: &mut GameState) {
     match state.current_screen {
         Screen::Menu => draw_menu(state).await,
         Screen::Battle => {
-            if let Some(battle) = &state.battle {
-                draw_battle(battle).await;
+            if let (Some(battle), Some(assets)) = (&state.battle, &state.assets) {
+                draw_battle(battle, assets).await;
             }
         }
         Screen::Victory => draw_victory_screen(state).await,
         Screen::Defeat => draw_defeat_screen(state).await,
+        Screen::Replay => draw_replay_screen(state).await,
+        Screen::Party => draw_party_screen(state).await,
+        Screen::Workshop => draw_workshop_screen(state).await,
+        Screen::Map => draw_map_screen(state).await,
     }
 }
 
@@ -155,25 +205,808 @@ async fn draw_menu(state: &mut GameState) {
     button.draw();
 
     if button.clicked() {
-        state.start_battle();
-        state.current_screen = Screen::Battle;
+        state.current_screen = Screen::Map;
     };
+
+    let replay_button = Button {
+        rect: Rect::new(250.0, 280.0, 200.0, 60.0),
+        label: "Last Replay".to_string(),
+        color: GRAY,
+    };
+
+    replay_button.draw();
+
+    if replay_button.clicked() {
+        if let Err(e) = state.load_replay(LAST_REPLAY_PATH) {
+            eprintln!("Could not load replay: {:#}", e);
+        }
+    }
+
+    for (slot, button) in slot_buttons("Load", 360.0).into_iter().enumerate() {
+        button.draw();
+        if button.clicked() {
+            if let Err(e) = state.load_slot(slot as u32 + 1) {
+                eprintln!("Could not load game: {:#}", e);
+            }
+        }
+    }
+
+    let party_button = Button {
+        rect: Rect::new(250.0, 420.0, 200.0, 60.0),
+        label: "Party".to_string(),
+        color: GRAY,
+    };
+
+    party_button.draw();
+
+    if party_button.clicked() {
+        open_party_screen(state);
+    }
+
+    let workshop_button = Button {
+        rect: Rect::new(470.0, 420.0, 200.0, 60.0),
+        label: "Workshop".to_string(),
+        color: GRAY,
+    };
+
+    workshop_button.draw();
+
+    if workshop_button.clicked() {
+        open_workshop_screen(state);
+    }
+
+    let difficulty_button = Button {
+        rect: Rect::new(250.0, 500.0, 420.0, 50.0),
+        label: format!("Difficulty: {:?}", state.difficulty),
+        color: DARKGRAY,
+    };
+
+    difficulty_button.draw();
+
+    if difficulty_button.clicked() {
+        state.difficulty = state.difficulty.next();
+    }
+}
+
+fn open_party_screen(state: &mut GameState) {
+    state.party_view = PartyView {
+        return_to: state.current_screen,
+        ..PartyView::default()
+    };
+    state.current_screen = Screen::Party;
+}
+
+fn open_workshop_screen(state: &mut GameState) {
+    state.workshop_view = WorkshopView {
+        return_to: state.current_screen,
+        ..WorkshopView::default()
+    };
+    state.current_screen = Screen::Workshop;
+}
+
+fn slot_buttons(action: &str, y: f32) -> Vec<Button> {
+    (1..=SAVE_SLOTS)
+        .map(|slot| Button {
+            rect: Rect::new(250.0 + (slot - 1) as f32 * 110.0, y, 100.0, 40.0),
+            label: format!("{} {}", action, slot),
+            color: DARKGRAY,
+        })
+        .collect()
 }
 
-async fn draw_victory_screen(_state: &mut GameState) {
+async fn draw_victory_screen(state: &mut GameState) {
     draw_text("VICTORY!", 250.0, 100.0, 50.0, GREEN);
 
+    let mut lines = Vec::new();
+    if let Some(rewards) = &state.rewards {
+        lines.push(format!("XP gained: {}", rewards.xp));
+        for (name, level) in &rewards.level_ups {
+            lines.push(format!("{} reached level {}!", name, level));
+        }
+        if !rewards.items.is_empty() {
+            let names: Vec<_> = rewards.items.iter().map(|i| i.name.as_str()).collect();
+            lines.push(format!("Items: {}", names.join(", ")));
+        }
+        if !rewards.materials.is_empty() {
+            let materials: Vec<_> = rewards
+                .materials
+                .iter()
+                .map(|(m, n)| format!("{} x{}", m, n))
+                .collect();
+            lines.push(format!("Materials: {}", materials.join(", ")));
+        }
+    }
+    for hero in &state.player_party {
+        let stats = hero.effective_stats();
+        lines.push(format!(
+            "{}  Lv {}  XP {}  HP {}/{}",
+            hero.name,
+            hero.level(),
+            hero.xp,
+            stats.hp,
+            stats.max_hp
+        ));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, 250.0, 150.0 + i as f32 * 25.0, 24.0, WHITE);
+    }
+
+    let buttons_y = 180.0 + lines.len() as f32 * 25.0;
+    let continue_button = Button {
+        rect: Rect::new(250.0, buttons_y, 200.0, 60.0),
+        label: "Continue".to_string(),
+        color: LIME,
+    };
     let exit_button = Button {
-        rect: Rect::new(250.0, 200.0, 200.0, 60.0),
+        rect: Rect::new(470.0, buttons_y, 200.0, 60.0),
         label: "Exit Game".to_string(),
-        color: LIME,
+        color: GRAY,
+    };
+    let party_button = Button {
+        rect: Rect::new(580.0, buttons_y + 80.0, 90.0, 40.0),
+        label: "Party".to_string(),
+        color: DARKGRAY,
+    };
+    let workshop_button = Button {
+        rect: Rect::new(680.0, buttons_y + 80.0, 110.0, 40.0),
+        label: "Workshop".to_string(),
+        color: DARKGRAY,
     };
 
+    continue_button.draw();
     exit_button.draw();
+    party_button.draw();
+    workshop_button.draw();
+
+    if continue_button.clicked() {
+        state.current_screen = Screen::Map;
+    }
+
+    if party_button.clicked() {
+        open_party_screen(state);
+        return;
+    }
+
+    if workshop_button.clicked() {
+        open_workshop_screen(state);
+        return;
+    }
 
     if exit_button.clicked() {
         std::process::exit(0);
     }
+
+    for (slot, button) in slot_buttons("Save", buttons_y + 80.0)
+        .into_iter()
+        .enumerate()
+    {
+        button.draw();
+        if button.clicked() {
+            match state.save_slot(slot as u32 + 1) {
+                Ok(()) => println!("Saved to slot {}", slot + 1),
+                Err(e) => eprintln!("Could not save game: {:#}", e),
+            }
+        }
+    }
+}
+
+/// A clickable spot on the party screen: an item that can be picked up, a place to drop the
+/// held item, or both.
+struct PartyWidget {
+    button: Button,
+    item: Option<ItemLocation>,
+    drop: Option<ItemDestination>,
+}
+
+fn party_widget(
+    rect: Rect,
+    label: String,
+    item: Option<ItemLocation>,
+    drop: Option<ItemDestination>,
+    held: Option<ItemLocation>,
+) -> PartyWidget {
+    let color = if item.is_some() && item == held {
+        ORANGE
+    } else if item.is_some() {
+        DARKBLUE
+    } else {
+        DARKGRAY
+    };
+    PartyWidget {
+        button: Button { rect, label, color },
+        item,
+        drop,
+    }
+}
+
+fn party_widgets(state: &GameState) -> Vec<PartyWidget> {
+    let view = &state.party_view;
+    let hero = view.hero;
+    let mut widgets = Vec::new();
+    let Some(selected) = state.player_party.get(hero) else {
+        return widgets;
+    };
+
+    for (slot_idx, &slot) in ItemSlot::ALL.iter().enumerate() {
+        let equipped = selected.inventory.equipped.get(&slot);
+        let label = format!(
+            "{:?}: {}",
+            slot,
+            equipped.map_or("-", |item| item.name.as_str())
+        );
+        widgets.push(party_widget(
+            Rect::new(20.0, 290.0 + slot_idx as f32 * 40.0, 260.0, 36.0),
+            label,
+            equipped.map(|_| ItemLocation::Equipped(hero, slot)),
+            Some(ItemDestination::Equip(hero, slot)),
+            view.held,
+        ));
+    }
+
+    let containers = [
+        (
+            300.0,
+            "Backpack",
+            ItemDestination::Backpack(hero),
+            selected
+                .inventory
+                .backpack
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| (ItemLocation::Backpack(hero, idx), item))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            560.0,
+            "Storage",
+            ItemDestination::Storage,
+            state
+                .storage
+                .items
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| (ItemLocation::Storage(idx), item))
+                .collect(),
+        ),
+    ];
+    for (x, title, destination, items) in containers {
+        widgets.push(party_widget(
+            Rect::new(x, 80.0, 220.0, 36.0),
+            title.to_string(),
+            None,
+            Some(destination),
+            view.held,
+        ));
+        for (row, (location, item)) in items.into_iter().enumerate() {
+            widgets.push(party_widget(
+                Rect::new(x, 122.0 + row as f32 * 36.0, 220.0, 32.0),
+                item.name.clone(),
+                Some(location),
+                Some(destination),
+                view.held,
+            ));
+        }
+    }
+
+    widgets
+}
+
+/// Stat lines of the character sheet, with the base value next to anything equipment changes.
+fn stat_lines(hero: &Hero) -> Vec<String> {
+    let base = &hero.stats;
+    let stats = hero.effective_stats();
+    let line = |name: &str, value: String, base_value: String| {
+        if value == base_value {
+            format!("{} {}", name, value)
+        } else {
+            format!("{} {} (base {})", name, value, base_value)
+        }
+    };
+    vec![
+        format!(
+            "{}  Lv {}  XP {}  Skill points {}",
+            hero.name,
+            hero.level(),
+            hero.xp,
+            hero.skill_points
+        ),
+        line(
+            "HP",
+            format!("{}/{}", stats.hp, stats.max_hp),
+            format!("{}/{}", base.hp, base.max_hp),
+        ),
+        line(
+            "Damage",
+            format!("{}-{}", stats.damage.0, stats.damage.1),
+            format!("{}-{}", base.damage.0, base.damage.1),
+        ),
+        line("Attack", stats.attack.to_string(), base.attack.to_string()),
+        line(
+            "Defense",
+            stats.defense.to_string(),
+            base.defense.to_string(),
+        ),
+        line(
+            "Initiative",
+            stats.initiative.to_string(),
+            base.initiative.to_string(),
+        ),
+        line(
+            "Movement",
+            stats.movement.to_string(),
+            base.movement.to_string(),
+        ),
+    ]
+}
+
+async fn draw_party_screen(state: &mut GameState) {
+    let hero_tabs: Vec<Button> = state
+        .player_party
+        .iter()
+        .enumerate()
+        .map(|(i, hero)| Button {
+            rect: Rect::new(20.0 + i as f32 * 160.0, 20.0, 150.0, 40.0),
+            label: hero.name.clone(),
+            color: if i == state.party_view.hero {
+                LIME
+            } else {
+                GRAY
+            },
+        })
+        .collect();
+    for (i, tab) in hero_tabs.iter().enumerate() {
+        tab.draw();
+        if tab.clicked() {
+            state.party_view.hero = i;
+        }
+    }
+
+    if let Some(hero) = state.player_party.get(state.party_view.hero) {
+        for (i, line) in stat_lines(hero).iter().enumerate() {
+            draw_text(line, 20.0, 100.0 + i as f32 * 24.0, 22.0, BLACK);
+        }
+    }
+
+    let skills_button = Button {
+        rect: Rect::new(240.0, 540.0, 200.0, 50.0),
+        label: if state.party_view.skills {
+            "Items"
+        } else {
+            "Skills"
+        }
+        .to_string(),
+        color: GRAY,
+    };
+    skills_button.draw();
+    if skills_button.clicked() {
+        state.party_view.skills = !state.party_view.skills;
+        state.party_view.held = None;
+    }
+
+    if state.party_view.skills {
+        draw_skill_tree(state);
+    } else {
+        draw_party_items(state);
+    }
+
+    let back_button = Button {
+        rect: Rect::new(20.0, 540.0, 200.0, 50.0),
+        label: "Back".to_string(),
+        color: GRAY,
+    };
+
+    back_button.draw();
+
+    if back_button.clicked() {
+        state.party_view.held = None;
+        state.current_screen = state.party_view.return_to;
+    }
+}
+
+/// Nodes of the selected hero's class tree. Unlocked nodes are green, ones the hero can unlock
+/// right now are blue.
+fn draw_skill_tree(state: &mut GameState) {
+    let hero_idx = state.party_view.hero;
+    let Some(hero) = state.player_party.get(hero_idx) else {
+        return;
+    };
+    let Some(tree) = state.content.skill_trees.get(&hero.class) else {
+        return;
+    };
+
+    let nodes: Vec<(Button, String, String)> = tree
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let color = if hero.unlocked_skills.contains(&node.id) {
+                DARKGREEN
+            } else if tree.unlockable(hero, &node.id).is_ok() {
+                DARKBLUE
+            } else {
+                DARKGRAY
+            };
+            let button = Button {
+                rect: Rect::new(300.0, 80.0 + i as f32 * 44.0, 460.0, 40.0),
+                label: format!("{} ({} SP)", node.name, node.cost),
+                color,
+            };
+            (button, node.id.clone(), node.description.clone())
+        })
+        .collect();
+
+    let (mx, my) = mouse_position();
+    for (button, id, description) in &nodes {
+        button.draw();
+        if button.rect.contains(vec2(mx, my)) {
+            draw_text(description, 300.0, 520.0, 22.0, BLACK);
+        }
+        if button.clicked() {
+            if let Err(e) = state.unlock_skill(hero_idx, id) {
+                eprintln!("Could not unlock skill: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Equipment, backpack and storage, with items moved by clicking one and then its
+/// destination, or by dragging it there.
+fn draw_party_items(state: &mut GameState) {
+    let widgets = party_widgets(state);
+    for widget in &widgets {
+        widget.button.draw();
+    }
+
+    let (mx, my) = mouse_position();
+    let hovered = widgets
+        .iter()
+        .find(|w| w.button.rect.contains(vec2(mx, my)));
+    let held = state.party_view.held;
+    if is_mouse_button_pressed(MouseButton::Left) {
+        match (held, hovered) {
+            (Some(from), Some(widget)) if widget.item == Some(from) => {
+                state.party_view.held = None;
+            }
+            (Some(from), Some(PartyWidget { drop: Some(to), .. })) => {
+                drop_held_item(state, from, *to);
+            }
+            (
+                _,
+                Some(PartyWidget {
+                    item: Some(item), ..
+                }),
+            ) => {
+                state.party_view.held = Some(*item);
+            }
+            _ => {}
+        }
+    } else if is_mouse_button_released(MouseButton::Left) {
+        if let (Some(from), Some(widget)) = (held, hovered) {
+            if let (Some(to), false) = (widget.drop, widget.item == Some(from)) {
+                drop_held_item(state, from, to);
+            }
+        }
+    }
+
+    if let Some(item) = state.party_view.held.and_then(|l| state.item_at(l)) {
+        draw_text(&item.name, mx + 12.0, my, 22.0, ORANGE);
+    }
+}
+
+fn drop_held_item(state: &mut GameState, from: ItemLocation, to: ItemDestination) {
+    state.party_view.held = None;
+    if let Err(e) = state.move_item(from, to) {
+        eprintln!("Could not move item: {:#}", e);
+    }
+}
+
+fn effect_text(effect: &Effect) -> String {
+    match effect {
+        Effect::ReduceMovement(amount, turns) => {
+            format!("-{} movement for {} turns", amount, turns)
+        }
+        Effect::Poison(damage, turns) => format!("Poison {} for {} turns", damage, turns),
+    }
+}
+
+/// What an item does, one line per property.
+fn item_lines(item: &Item) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(slot) = item.slot {
+        lines.push(format!("Worn as {:?}", slot));
+    }
+    for &(stat, amount) in &item.stat_changes {
+        lines.push(format!("{:+} {:?}", amount, stat));
+    }
+    for effect in &item.effects {
+        lines.push(format!("On hit: {}", effect_text(effect)));
+    }
+    match &item.consumable {
+        Some(Consumable::Heal(amount)) => lines.push(format!("Heals {} HP", amount)),
+        Some(Consumable::Antidote) => lines.push("Cures poison".to_string()),
+        Some(Consumable::Throwable {
+            range,
+            damage,
+            effect,
+        }) => {
+            lines.push(format!(
+                "Thrown up to {} hexes for {} damage",
+                range, damage
+            ));
+            if let Some(effect) = effect {
+                lines.push(effect_text(effect));
+            }
+        }
+        None => {}
+    }
+    lines
+}
+
+fn materials_text(materials: &[(String, u32)]) -> String {
+    let materials: Vec<_> = materials
+        .iter()
+        .map(|(m, n)| format!("{} x{}", m, n))
+        .collect();
+    materials.join(", ")
+}
+
+async fn draw_workshop_screen(state: &mut GameState) {
+    draw_text("WORKSHOP", 20.0, 40.0, 40.0, BLACK);
+
+    let stash: Vec<_> = state
+        .materials
+        .iter()
+        .map(|(m, &n)| (m.clone(), n))
+        .collect();
+    let stash = if stash.is_empty() {
+        "none".to_string()
+    } else {
+        materials_text(&stash)
+    };
+    draw_text(&format!("Materials: {}", stash), 20.0, 70.0, 22.0, BLACK);
+
+    let status = state.craft_status();
+    let selected = state.workshop_view.blueprint.clone();
+    for (i, craft) in status.iter().enumerate() {
+        let result = state
+            .content
+            .blueprints
+            .get(&craft.blueprint_id)
+            .and_then(|b| state.content.items.get(&b.result));
+        let button = Button {
+            rect: Rect::new(20.0, 90.0 + i as f32 * 44.0, 220.0, 40.0),
+            label: result.map_or(craft.blueprint_id.clone(), |item| item.name.clone()),
+            color: if selected.as_ref() == Some(&craft.blueprint_id) {
+                ORANGE
+            } else if craft.craftable() {
+                LIME
+            } else {
+                GRAY
+            },
+        };
+        button.draw();
+        if button.clicked() {
+            state.workshop_view.blueprint = Some(craft.blueprint_id.clone());
+        }
+    }
+
+    if let Some(craft) = status
+        .iter()
+        .find(|c| Some(&c.blueprint_id) == selected.as_ref())
+    {
+        draw_blueprint_details(state, &craft.blueprint_id);
+
+        let craft_button = Button {
+            rect: Rect::new(260.0, 440.0, 200.0, 50.0),
+            label: "Craft".to_string(),
+            color: if craft.craftable() { LIME } else { DARKGRAY },
+        };
+        craft_button.draw();
+        if craft_button.clicked() {
+            match state.craft(&craft.blueprint_id) {
+                Ok(item) => println!("Crafted {}", item.name),
+                Err(e) => eprintln!("Could not craft: {:#}", e),
+            }
+        }
+    }
+
+    draw_text("Salvage", 540.0, 110.0, 24.0, BLACK);
+    let salvageable: Vec<_> = state
+        .owned_items()
+        .into_iter()
+        .map(|(location, item)| (location, item.name.clone(), item.decompose()))
+        .filter(|(_, _, materials)| !materials.is_empty())
+        .collect();
+    for (row, (location, name, materials)) in salvageable.iter().enumerate() {
+        let y = 120.0 + row as f32 * 52.0;
+        let button = Button {
+            rect: Rect::new(540.0, y, 240.0, 32.0),
+            label: name.clone(),
+            color: DARKBLUE,
+        };
+        button.draw();
+        draw_text(&materials_text(materials), 545.0, y + 46.0, 16.0, BLACK);
+        if button.clicked() {
+            match state.salvage(*location) {
+                Ok(materials) => {
+                    println!("Salvaged {} for {}", name, materials_text(&materials))
+                }
+                Err(e) => eprintln!("Could not salvage: {:#}", e),
+            }
+            // Later locations shifted, the list is rebuilt next frame.
+            break;
+        }
+    }
+
+    let back_button = Button {
+        rect: Rect::new(20.0, 540.0, 200.0, 50.0),
+        label: "Back".to_string(),
+        color: GRAY,
+    };
+
+    back_button.draw();
+
+    if back_button.clicked() {
+        state.current_screen = state.workshop_view.return_to;
+    }
+}
+
+/// Cost against the stash and a preview of the crafted item.
+fn draw_blueprint_details(state: &GameState, blueprint_id: &str) {
+    let Some(blueprint) = state.content.blueprints.get(blueprint_id) else {
+        return;
+    };
+    let mut y = 110.0;
+    if let Some(item) = state.content.items.get(&blueprint.result) {
+        draw_text(&item.name, 260.0, y, 28.0, BLACK);
+        y += 30.0;
+        for line in item_lines(item) {
+            draw_text(&line, 260.0, y, 22.0, DARKBLUE);
+            y += 24.0;
+        }
+    }
+
+    y += 10.0;
+    draw_text("Cost", 260.0, y, 24.0, BLACK);
+    y += 26.0;
+    for (material, &amount) in &blueprint.cost {
+        let have = state.materials.count(material);
+        let color = if have >= amount { DARKGREEN } else { RED };
+        draw_text(
+            &format!("{} {}/{}", material, have, amount),
+            260.0,
+            y,
+            22.0,
+            color,
+        );
+        y += 24.0;
+    }
+}
+
+const MAP_NODE_RADIUS: f32 = 22.0;
+
+fn node_label(kind: NodeKind) -> &'static str {
+    match kind {
+        NodeKind::Battle => "Battle",
+        NodeKind::EliteBattle => "Elite",
+        NodeKind::Shrine => "Shrine",
+        NodeKind::Merchant => "Merchant",
+        NodeKind::CraftingCamp => "Camp",
+        NodeKind::Boss => "Boss",
+    }
+}
+
+/// Screen position of every node: layers left to right, nodes of a layer spread top to bottom.
+fn map_node_positions(map: &AdventureMap) -> Vec<Vec2> {
+    let layers = map.layer_count().max(2);
+    let mut positions = vec![Vec2::ZERO; map.nodes.len()];
+    for layer in 0..layers {
+        let ids = map.layer(layer);
+        for (row, &id) in ids.iter().enumerate() {
+            positions[id] = vec2(
+                60.0 + layer as f32 * 680.0 / (layers - 1) as f32,
+                80.0 + (row + 1) as f32 * 420.0 / (ids.len() + 1) as f32,
+            );
+        }
+    }
+    positions
+}
+
+async fn draw_map_screen(state: &mut GameState) {
+    draw_text("ADVENTURE MAP", 20.0, 40.0, 40.0, BLACK);
+
+    let Some(map) = &state.progress.map else {
+        state.current_screen = Screen::Menu;
+        return;
+    };
+    let positions = map_node_positions(map);
+    let choices = map.choices();
+    let finished = map.is_finished();
+
+    for (id, node) in map.nodes.iter().enumerate() {
+        for &next in &node.next {
+            let on_path = map.path.windows(2).any(|w| w == [id, next]);
+            let (from, to) = (positions[id], positions[next]);
+            let color = if on_path { ORANGE } else { GRAY };
+            draw_line(from.x, from.y, to.x, to.y, 2.0, color);
+        }
+    }
+
+    let mut clicked = None;
+    for (id, node) in map.nodes.iter().enumerate() {
+        let pos = positions[id];
+        let color = if map.path.contains(&id) {
+            DARKGRAY
+        } else if choices.contains(&id) {
+            LIME
+        } else {
+            GRAY
+        };
+        draw_circle(pos.x, pos.y, MAP_NODE_RADIUS, color);
+        if map.current() == Some(id) {
+            draw_circle_lines(pos.x, pos.y, MAP_NODE_RADIUS + 3.0, 3.0, ORANGE);
+        }
+        let label = node_label(node.kind);
+        let dims = measure_text(label, None, 18, 1.0);
+        draw_text(
+            label,
+            pos.x - dims.width / 2.0,
+            pos.y + MAP_NODE_RADIUS + 16.0,
+            18.0,
+            BLACK,
+        );
+
+        if is_mouse_button_pressed(MouseButton::Left)
+            && choices.contains(&id)
+            && Vec2::from(mouse_position()).distance(pos) <= MAP_NODE_RADIUS
+        {
+            clicked = Some(id);
+        }
+    }
+
+    if finished {
+        draw_text("Campaign complete!", 250.0, 530.0, 30.0, DARKGREEN);
+    }
+
+    if let Some(id) = clicked {
+        if let Err(e) = state.visit_node(id) {
+            eprintln!("Could not enter node: {:#}", e);
+        }
+        return;
+    }
+
+    let menu_button = Button {
+        rect: Rect::new(20.0, 550.0, 100.0, 40.0),
+        label: "Menu".to_string(),
+        color: GRAY,
+    };
+    let party_button = Button {
+        rect: Rect::new(130.0, 550.0, 100.0, 40.0),
+        label: "Party".to_string(),
+        color: DARKGRAY,
+    };
+
+    menu_button.draw();
+    party_button.draw();
+
+    if menu_button.clicked() {
+        state.current_screen = Screen::Menu;
+    }
+
+    if party_button.clicked() {
+        open_party_screen(state);
+        return;
+    }
+
+    for (slot, button) in slot_buttons("Save", 550.0).into_iter().enumerate() {
+        button.draw();
+        if button.clicked() {
+            match state.save_slot(slot as u32 + 1) {
+                Ok(()) => println!("Saved to slot {}", slot + 1),
+                Err(e) => eprintln!("Could not save game: {:#}", e),
+            }
+        }
+    }
 }
 
 async fn draw_defeat_screen(_state: &mut GameState) {
@@ -192,7 +1025,82 @@ async fn draw_defeat_screen(_state: &mut GameState) {
     }
 }
 
-async fn draw_battle(battle: &BattleState) {
+async fn draw_replay_screen(state: &mut GameState) {
+    let (Some(replay), Some(assets)) = (&mut state.replay, &state.assets) else {
+        state.current_screen = Screen::Menu;
+        return;
+    };
+
+    draw_battlefield(&replay.battle, assets);
+
+    let (step, total) = replay.position();
+    let status = if replay.diverged {
+        "DIVERGED"
+    } else if replay.is_finished() {
+        "FINISHED"
+    } else if replay.paused {
+        "PAUSED"
+    } else {
+        "PLAYING"
+    };
+    draw_text(
+        &format!("Replay {}/{} - {}", step, total, status),
+        20.0,
+        30.0,
+        30.0,
+        BLACK,
+    );
+    draw_text(
+        &format!("Seed: {}", replay.battle.seed),
+        20.0,
+        55.0,
+        20.0,
+        DARKGRAY,
+    );
+
+    for (i, button) in replay_buttons(replay).iter().enumerate() {
+        button.draw();
+        if !button.clicked() {
+            continue;
+        }
+        match i {
+            0 => replay.paused = !replay.paused,
+            1 => {
+                replay.paused = true;
+                if !replay.is_finished() {
+                    replay.step();
+                }
+            }
+            _ => {
+                state.replay = None;
+                state.current_screen = Screen::Menu;
+                return;
+            }
+        }
+    }
+}
+
+fn replay_buttons(replay: &BattleReplay) -> Vec<Button> {
+    let y = screen_height() - 60.0;
+    let labels = [if replay.paused { "Play" } else { "Pause" }, "Step", "Back"];
+
+    labels
+        .iter()
+        .enumerate()
+        .map(|(i, label)| Button {
+            rect: Rect::new(50.0 + i as f32 * 160.0, y, 150.0, 40.0),
+            label: label.to_string(),
+            color: GRAY,
+        })
+        .collect()
+}
+
+async fn draw_battle(battle: &BattleState, assets: &Assets) {
+    draw_battlefield(battle, assets);
+    draw_hud(battle);
+}
+
+fn draw_battlefield(battle: &BattleState, assets: &Assets) {
     draw_hex_grid(battle.grid_width, battle.grid_height);
     draw_terrain(battle);
 
@@ -201,9 +1109,7 @@ async fn draw_battle(battle: &BattleState) {
 
     draw_preview(battle);
 
-    draw_units(battle);
-
-    draw_hud(battle);
+    draw_units(battle, assets);
 }
 
 fn draw_hex_grid(grid_width: i32, grid_height: i32) {
@@ -220,7 +1126,7 @@ fn draw_preview(battle: &BattleState) {
     match battle.input_mode {
         InputMode::Normal => {}
         InputMode::Movement => draw_movement_preview(battle),
-        InputMode::AbilityTarget(_) => draw_ability_preview(battle),
+        InputMode::AbilityTarget(_) | InputMode::ItemTarget(_) => draw_ability_preview(battle),
     }
 }
 
@@ -229,12 +1135,31 @@ fn draw_ability_preview(battle: &BattleState) {
         let (x, y) = hex_to_screen(*hex, battle.grid_width, battle.grid_height);
         draw_poly(x, y, 6, HEX_RADIUS, 0.0, Color::new(1.0, 0.0, 0.0, 0.35));
     }
+    // In range, but something is in the way.
+    for hex in &battle.selected_ability_blocked {
+        let (x, y) = hex_to_screen(*hex, battle.grid_width, battle.grid_height);
+        draw_poly(x, y, 6, HEX_RADIUS, 0.0, Color::new(0.2, 0.2, 0.2, 0.35));
+    }
 }
 
 fn draw_movement_preview(battle: &BattleState) {
-    for hex in battle.selected_unit_range.keys() {
+    let movement = battle
+        .selected_unit
+        .and_then(|u| battle.unit_current_movement(u))
+        .unwrap_or(0);
+
+    for (hex, (cost, _)) in &battle.selected_unit_range {
         let (x, y) = hex_to_screen(*hex, battle.grid_width, battle.grid_height);
         draw_poly(x, y, 6, HEX_RADIUS, 0.0, Color::new(0.4, 0.6, 1.0, 0.35));
+
+        // Movement left after walking here
+        draw_text(
+            &(movement - cost).to_string(),
+            x + HEX_RADIUS * 0.35,
+            y - HEX_RADIUS * 0.35,
+            18.0,
+            DARKBLUE,
+        );
     }
 }
 
@@ -271,25 +1196,45 @@ fn draw_terrain(battle: &BattleState) {
         if !info.passable {
             draw_text("X", x - 5.0, y + 5.0, 20.0, RED);
         }
+
+        for object in &info.objects {
+            let (symbol, color) = match object {
+                Object::HealingShrine => ("+", WHITE),
+                Object::Trap => ("^", MAROON),
+            };
+            draw_text(symbol, x - 6.0, y - HEX_RADIUS * 0.4, 26.0, color);
+        }
     });
 }
 
-fn draw_units(battle: &BattleState) {
+fn unit_texture<'a>(assets: &'a Assets, unit: &dyn UnitRender) -> Option<&'a Texture2D> {
+    match unit.unit_ref() {
+        UnitRef::Hero(_) => Some(&assets.hero),
+        UnitRef::Enemy(_) => assets.enemy.get(unit.name()),
+    }
+}
+
+fn draw_units(battle: &BattleState, assets: &Assets) {
     for unit in battle.units_for_render() {
         let (x, y) = hex_to_screen(unit.hex(), battle.grid_width, battle.grid_height);
-        draw_texture_ex(
-            unit.texture(),
-            x - HEX_RADIUS * UNIT_SCALE,
-            y - HEX_RADIUS * UNIT_SCALE,
-            WHITE,
-            DrawTextureParams {
-                dest_size: Some(vec2(
-                    HEX_RADIUS * 2.0 * UNIT_SCALE,
-                    HEX_RADIUS * 2.0 * UNIT_SCALE,
-                )),
-                ..Default::default()
-            },
-        );
+
+        if let Some(texture) = unit_texture(assets, unit) {
+            draw_texture_ex(
+                texture,
+                x - HEX_RADIUS * UNIT_SCALE,
+                y - HEX_RADIUS * UNIT_SCALE,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(
+                        HEX_RADIUS * 2.0 * UNIT_SCALE,
+                        HEX_RADIUS * 2.0 * UNIT_SCALE,
+                    )),
+                    ..Default::default()
+                },
+            );
+        } else {
+            draw_text(unit.name(), x - HEX_RADIUS * 0.5, y, 18.0, BLACK);
+        }
 
         draw_health_bar(
             x - HEX_RADIUS,
@@ -323,8 +1268,18 @@ fn draw_hud(battle: &BattleState) {
         30.0,
         BLACK,
     );
+    draw_text(
+        &format!("Seed: {}", battle.seed),
+        20.0,
+        55.0,
+        20.0,
+        DARKGRAY,
+    );
     draw_end_turn_button(battle);
     draw_abilities(battle);
+    for button in item_buttons(battle) {
+        button.draw();
+    }
 }
 
 fn draw_abilities(battle: &BattleState) {
@@ -356,7 +1311,7 @@ fn ability_buttons(battle: &BattleState) -> Vec<Button> {
         }
 
         // Optional cancel button
-        if battle.selected_ability.is_some() {
+        if battle.selected_ability.is_some() || battle.selected_item.is_some() {
             let cancel_x = start_x + hero.abilities.len() as f32 * (button_width + spacing);
             buttons.push(Button {
                 rect: Rect::new(cancel_x, y, 50.0, button_height),
@@ -369,11 +1324,42 @@ fn ability_buttons(battle: &BattleState) -> Vec<Button> {
     buttons
 }
 
+/// Consumables of the selected hero, one row above the abilities.
+fn item_buttons(battle: &BattleState) -> Vec<Button> {
+    let Some(UnitRef::Hero(hero_id)) = battle.selected_unit else {
+        return Vec::new();
+    };
+    let hero = battle.hero(hero_id).unwrap();
+
+    let button_width = 150.0;
+    let spacing = 10.0;
+    let y = screen_height() - 110.0;
+
+    hero.items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| Button {
+            rect: Rect::new(
+                50.0 + i as f32 * (button_width + spacing),
+                y,
+                button_width,
+                40.0,
+            ),
+            label: item.name.clone(),
+            color: if hero.action_available {
+                BLUE
+            } else {
+                DARKGRAY
+            },
+        })
+        .collect()
+}
+
 fn draw_end_turn_button(battle: &BattleState) {
     end_turn_button(battle).draw();
 }
 
-fn end_turn_button(battle: &BattleState) -> Button {
+fn end_turn_button(_battle: &BattleState) -> Button {
     Button {
         rect: Rect::new(600.0, 20.0, 150.0, 50.0),
         label: "End Turn".to_string(),